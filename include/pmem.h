@@ -0,0 +1,107 @@
+/* Generated with cbindgen from src/system/ffi.rs, don't edit by hand */
+
+#ifndef PMEM_H
+#define PMEM_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * A cluster and the runtime its calls block on. It's started once, with
+ * the configuration it was created with.
+ */
+typedef struct PmemHandle PmemHandle;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Create a handle from a JSON configuration, the Node bindings' fields,
+ * applied over `pmem.toml` and the `PMEM_*` variables. NULL applies none.
+ *
+ * # Safety
+ *
+ * `config_json` is NULL or a NUL-terminated string.
+ */
+PmemHandle *pmem_init(const char *config_json);
+
+/**
+ * Start the embedded cluster, or connect to the external server
+ *
+ * # Safety
+ *
+ * `handle` comes from `pmem_init` and wasn't freed.
+ */
+int pmem_start(PmemHandle *handle);
+
+/**
+ * Create a database, named by pmem when `name` is NULL, and return its URI
+ *
+ * # Safety
+ *
+ * `handle` comes from `pmem_init` and wasn't freed, `name` is NULL or a
+ * NUL-terminated string.
+ */
+char *pmem_create_db(PmemHandle *handle, const char *name);
+
+/**
+ * Drop a database
+ *
+ * # Safety
+ *
+ * `handle` comes from `pmem_init` and wasn't freed, `name` is a
+ * NUL-terminated string.
+ */
+int pmem_drop_db(PmemHandle *handle, const char *name);
+
+/**
+ * Run the sqlx migrations in `dir` on a database
+ *
+ * # Safety
+ *
+ * `handle` comes from `pmem_init` and wasn't freed, `name` and `dir` are
+ * NUL-terminated strings.
+ */
+int pmem_migrate(PmemHandle *handle, const char *name, const char *dir);
+
+/**
+ * Stop the cluster. The handle still has to be freed.
+ *
+ * # Safety
+ *
+ * `handle` comes from `pmem_init` and wasn't freed.
+ */
+int pmem_stop(PmemHandle *handle);
+
+/**
+ * Free a handle, stopping its cluster if it's still running
+ *
+ * # Safety
+ *
+ * `handle` is NULL or comes from `pmem_init` and wasn't freed.
+ */
+void pmem_free(PmemHandle *handle);
+
+/**
+ * What made the last call on this thread fail, NULL if it succeeded. The
+ * string belongs to pmem and is valid until the thread's next call.
+ */
+const char *pmem_last_error(void);
+
+/**
+ * Free a string pmem returned
+ *
+ * # Safety
+ *
+ * `s` is NULL or comes from pmem and wasn't freed.
+ */
+void pmem_string_free(char *s);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* PMEM_H */
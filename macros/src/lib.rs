@@ -0,0 +1,88 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, AttributeArgs, ItemFn, Lit, Meta, NestedMeta};
+
+/// Run an async test against a fresh database on the process' shared
+/// embedded cluster. The test takes the database as a `PgPool` or as its URI
+/// (a `String`), or nothing at all.
+///
+/// `migrations = "dir"` runs the sqlx migrations in `dir`, relative to the
+/// crate's manifest, first. The database is dropped after the test, unless it
+/// failed and `keep_on_failure = true` is set.
+#[proc_macro_attribute]
+pub fn test(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as AttributeArgs);
+    let input = parse_macro_input!(item as ItemFn);
+    match expand(args, input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(args: AttributeArgs, input: ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    let mut migrations = quote!(None);
+    let mut keep_on_failure = false;
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("migrations") => {
+                match nv.lit {
+                    Lit::Str(dir) => {
+                        migrations = quote!(Some(concat!(env!("CARGO_MANIFEST_DIR"), "/", #dir)));
+                    }
+                    lit => return Err(syn::Error::new_spanned(lit, "expected a directory")),
+                }
+            }
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("keep_on_failure") => {
+                match nv.lit {
+                    Lit::Bool(keep) => keep_on_failure = keep.value,
+                    lit => return Err(syn::Error::new_spanned(lit, "expected true or false")),
+                }
+            }
+            arg => {
+                return Err(syn::Error::new_spanned(
+                    arg,
+                    "expected `migrations = \"...\"` or `keep_on_failure = ...`",
+                ))
+            }
+        }
+    }
+
+    let sig = &input.sig;
+    if sig.asyncness.is_none() {
+        return Err(syn::Error::new_spanned(
+            sig.fn_token,
+            "pmem::test functions must be async",
+        ));
+    }
+    if sig.inputs.len() > 1 {
+        return Err(syn::Error::new_spanned(
+            &sig.inputs,
+            "pmem::test functions take at most the database",
+        ));
+    }
+    let name = &sig.ident;
+    let output = &sig.output;
+    let attrs = &input.attrs;
+    let vis = &input.vis;
+    let inputs = &sig.inputs;
+    let body = &input.block;
+    let call = match inputs.is_empty() {
+        true => quote!(inner()),
+        false => quote!(inner(::pmem::__testing::TestArg::from_uri(&uri))),
+    };
+
+    Ok(quote! {
+        #[::core::prelude::v1::test]
+        #(#attrs)*
+        #vis fn #name() #output {
+            async fn inner(#inputs) #output #body
+
+            let options = ::pmem::__testing::TestOptions {
+                name: concat!(module_path!(), "::", stringify!(#name)),
+                migrations: #migrations,
+                keep_on_failure: #keep_on_failure,
+            };
+            ::pmem::__testing::run(options, |uri: String| async move { #call.await })
+        }
+    })
+}
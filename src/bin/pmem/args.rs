@@ -0,0 +1,145 @@
+use std::convert::TryInto;
+
+use anyhow::anyhow;
+use pmem::{Config, ConfigBuilder, ConfigError};
+
+/// Where the CLI keeps its cluster unless `--root-path` says otherwise
+pub const DEFAULT_ROOT_PATH: &str = ".pmem";
+
+/// A command line: the subcommand, its `--key value` (or `--key=value`)
+/// options, its positional arguments and whatever follows `--`
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    pub command: Option<String>,
+    pub options: Vec<(String, String)>,
+    pub positional: Vec<String>,
+    pub trailing: Vec<String>,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                parsed.trailing = args.collect();
+                break;
+            }
+            match arg.strip_prefix("--") {
+                Some(option) => {
+                    let (key, value) = match option.split_once('=') {
+                        Some((key, value)) => (key.to_string(), value.to_string()),
+                        None => {
+                            let value = args
+                                .next()
+                                .ok_or_else(|| anyhow!("--{} needs a value", option))?;
+                            (option.to_string(), value)
+                        }
+                    };
+                    parsed.options.push((key, value));
+                }
+                None if parsed.command.is_none() => parsed.command = Some(arg),
+                None => parsed.positional.push(arg),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Remove a command's own option, the last one wins
+    pub fn take(&mut self, key: &str) -> Option<String> {
+        let mut value = None;
+        self.options.retain(|(k, v)| {
+            let matches = k == key;
+            if matches {
+                value = Some(v.clone());
+            }
+            !matches
+        });
+        value
+    }
+
+    /// Exactly `N` positional arguments
+    pub fn positional<const N: usize>(&self, usage: &str) -> anyhow::Result<[&str; N]> {
+        let args: Vec<&str> = self.positional.iter().map(String::as_str).collect();
+        args.try_into()
+            .map_err(|_| anyhow!("usage: pmem {}", usage))
+    }
+
+    /// The cluster configuration: the CLI's defaults, then `pmem.toml` and
+    /// the `PMEM_*` variables, then the remaining options. They're the
+    /// `ConfigDatabase` fields spelled with dashes.
+    pub fn config(&self) -> anyhow::Result<Config> {
+        let mut builder = Config::builder()
+            .root_path(DEFAULT_ROOT_PATH)
+            .reuse(true)
+            .load()?;
+        for (key, value) in &self.options {
+            builder = apply(builder, key, value)?;
+        }
+        Ok(builder.build())
+    }
+}
+
+fn apply(builder: ConfigBuilder, key: &str, value: &str) -> Result<ConfigBuilder, ConfigError> {
+    match key {
+        "uri" => Ok(builder.external(value)),
+        _ => builder.set(key, value),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use pmem::{AuthMethod, LogFormat};
+
+    use super::*;
+
+    fn args(line: &str) -> Args {
+        Args::parse(line.split_whitespace().map(String::from)).unwrap()
+    }
+
+    #[test]
+    fn test_args_split_options_positionals_and_the_command() {
+        let mut parsed = args("migrate --port 5499 app --tls=true migrations -- psql -c x");
+        assert_eq!(parsed.command.as_deref(), Some("migrate"));
+        assert_eq!(parsed.positional, vec!["app", "migrations"]);
+        assert_eq!(parsed.trailing, vec!["psql", "-c", "x"]);
+        assert_eq!(
+            parsed.positional::<2>("migrate <db> <dir>").unwrap(),
+            ["app", "migrations"]
+        );
+        assert!(parsed.positional::<1>("drop <db>").is_err());
+        assert_eq!(parsed.take("tls").as_deref(), Some("true"));
+        assert_eq!(parsed.take("tls"), None);
+        assert_eq!(
+            parsed.options,
+            vec![("port".to_string(), "5499".to_string())]
+        );
+
+        assert!(Args::parse(vec!["create".to_string(), "--port".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_args_read_the_config_fields() {
+        let config = args("start").config().unwrap();
+        assert_eq!(config.root_path.as_deref(), Some(DEFAULT_ROOT_PATH));
+        assert_eq!(config.reuse, Some(true));
+
+        let config = args(
+            "start --root-path /tmp/db --port 5499 --reuse false --auth-method scram-sha-256 \
+             --log-format json --timeout 30",
+        )
+        .config()
+        .unwrap();
+        assert_eq!(config.root_path.as_deref(), Some("/tmp/db"));
+        assert_eq!(config.port, Some(5499));
+        assert_eq!(config.reuse, Some(false));
+        assert_eq!(config.auth_method, Some(AuthMethod::ScramSha256));
+        assert_eq!(config.log_format, Some(LogFormat::Json));
+        assert_eq!(config.timeout, Some(Duration::from_secs(30)));
+
+        assert!(args("start --tls yes").config().is_err());
+        assert!(args("start --colour red").config().is_err());
+    }
+}
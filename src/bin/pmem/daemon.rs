@@ -0,0 +1,621 @@
+//! `pmem daemon`: a JSON HTTP API over one cluster, for clients that can't
+//! use the library or the Node bindings. Every database created through it
+//! is leased: it's dropped once the lease runs out without being renewed, or,
+//! if the client asked for it, as soon as the connection it was created on
+//! closes.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    env, fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use hyper::{
+    header, server::conn::Http, service::service_fn, Body, Method, Request, Response, StatusCode,
+};
+use percent_encoding::percent_decode_str;
+use pmem::{Cluster, Database};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use sqlx::{Column, Row};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::{MappedMutexGuard, Mutex as AsyncMutex, MutexGuard},
+};
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+use super::{args::Args, root_path, row_values, shutdown_signal, start_or_attach};
+
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:7744";
+/// How long a database lives without its lease being renewed
+const DEFAULT_LEASE: Duration = Duration::from_secs(600);
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+/// How long to back off when accepting a connection fails
+const ACCEPT_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+const TOKEN_LENGTH: usize = 32;
+const REQUEST_LOG_LEVEL: &str = "warn,pmem::daemon=info";
+
+pub async fn daemon(mut args: Args) -> anyhow::Result<i32> {
+    let listen = args.take("listen");
+    let socket = args.take("socket").map(PathBuf::from);
+    let lease = match args.take("lease") {
+        Some(secs) => Duration::from_secs(secs.parse().context("Invalid value for --lease")?),
+        None => DEFAULT_LEASE,
+    };
+    let (token, generated) = match args.take("token").or_else(|| env::var("PMEM_TOKEN").ok()) {
+        Some(token) => (token, false),
+        None => (generate_token(), true),
+    };
+    // The requests are what a daemon logs
+    if !args.options.iter().any(|(key, _)| key == "log-level") {
+        args.options
+            .push(("log-level".to_string(), REQUEST_LOG_LEVEL.to_string()));
+    }
+    let config = args.config()?;
+    let _ = pmem::init_logging(&config.log_config());
+
+    let listener = match &socket {
+        Some(path) => Listener::bind_socket(path)?,
+        None => {
+            let addr = listen.as_deref().unwrap_or(DEFAULT_LISTEN);
+            let listener = TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("Unable to listen on {}", addr))?;
+            println!("Listening on http://{}", listener.local_addr()?);
+            Listener::Tcp(listener)
+        }
+    };
+    if generated {
+        println!("Token: {}", token);
+    }
+
+    let root_path = root_path(&config);
+    let (cluster, attached) = start_or_attach(config).await?;
+    let daemon = Arc::new(Daemon {
+        cluster: AsyncMutex::new(Some(cluster)),
+        leases: Mutex::new(Leases::default()),
+        token,
+        lease,
+        root_path,
+        connections: AtomicU64::new(0),
+    });
+
+    tokio::spawn(reap(daemon.clone()));
+    tokio::select! {
+        _ = listener.serve(daemon.clone()) => {}
+        _ = shutdown_signal() => log::info!("Shutting down"),
+    }
+
+    let names = daemon.leases.lock().unwrap().release_all();
+    for name in names {
+        daemon.drop_database(&name).await;
+    }
+    if let Some(path) = &socket {
+        let _ = fs::remove_file(path);
+    }
+    // Requests still coming in on open connections fail from here on
+    let cluster = daemon.cluster.lock().await.take();
+    if let (Some(cluster), false) = (cluster, attached) {
+        cluster.stop().await?;
+    }
+    Ok(0)
+}
+
+fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// A Unix socket only its owner can connect to
+    #[cfg(unix)]
+    fn bind_socket(path: &Path) -> anyhow::Result<Self> {
+        // Left behind by a previous daemon
+        let _ = fs::remove_file(path);
+        let listener =
+            UnixListener::bind(path).with_context(|| format!("Unable to listen on {:?}", path))?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        println!("Listening on {}", path.display());
+        Ok(Listener::Unix(listener))
+    }
+
+    #[cfg(not(unix))]
+    fn bind_socket(_path: &Path) -> anyhow::Result<Self> {
+        anyhow::bail!("--socket is only supported on unix, use --listen")
+    }
+
+    /// Accept connections until the daemon shuts down. Failing to accept one
+    /// (out of file descriptors, or the client gave up) isn't the daemon's
+    /// end.
+    async fn serve(&self, daemon: Arc<Daemon>) {
+        loop {
+            let res = match self {
+                Listener::Tcp(listener) => listener
+                    .accept()
+                    .await
+                    .map(|(stream, _)| spawn_connection(daemon.clone(), stream)),
+                #[cfg(unix)]
+                Listener::Unix(listener) => listener
+                    .accept()
+                    .await
+                    .map(|(stream, _)| spawn_connection(daemon.clone(), stream)),
+            };
+            if let Err(e) = res {
+                log::warn!("Unable to accept a connection: {}", e);
+                tokio::time::sleep(ACCEPT_RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Serve one client connection, its bound leases end with it
+fn spawn_connection<S>(daemon: Arc<Daemon>, stream: S)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let connection = daemon.connections.fetch_add(1, Ordering::Relaxed);
+    tokio::spawn(async move {
+        let handler = daemon.clone();
+        let service = service_fn(move |req| handle(handler.clone(), connection, req));
+        if let Err(e) = Http::new()
+            .http1_only(true)
+            .serve_connection(stream, service)
+            .await
+        {
+            log::debug!("Connection {} failed: {:?}", connection, e);
+        }
+        let names = daemon.leases.lock().unwrap().disconnected(connection);
+        for name in names {
+            log::info!("Client of {} disconnected, dropping it", name);
+            daemon.drop_database(&name).await;
+        }
+    });
+}
+
+/// Drop the databases whose lease ran out
+async fn reap(daemon: Arc<Daemon>) {
+    let mut interval = tokio::time::interval(REAP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let names = daemon.leases.lock().unwrap().expired(Instant::now());
+        for name in names {
+            log::info!("Lease on {} expired, dropping it", name);
+            daemon.drop_database(&name).await;
+        }
+    }
+}
+
+struct Daemon {
+    /// Only held to create and drop databases, migrations and SQL run on
+    /// connections of their own. Taken out to be stopped when the daemon
+    /// shuts down.
+    cluster: AsyncMutex<Option<Cluster>>,
+    leases: Mutex<Leases>,
+    token: String,
+    /// The lease databases get unless they ask for another one
+    lease: Duration,
+    root_path: PathBuf,
+    connections: AtomicU64,
+}
+
+impl Daemon {
+    async fn cluster(&self) -> Result<MappedMutexGuard<'_, Cluster>, ApiError> {
+        MutexGuard::try_map(self.cluster.lock().await, Option::as_mut).map_err(|_| {
+            ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "The daemon is shutting down",
+            )
+        })
+    }
+
+    /// A database the client holds a lease on, renewing it
+    async fn database(&self, name: &str) -> Result<Database, ApiError> {
+        self.renew(name)?;
+        Ok(self.cluster().await?.database(name)?)
+    }
+
+    async fn drop_database(&self, name: &str) {
+        let mut cluster = match self.cluster().await {
+            Ok(cluster) => cluster,
+            Err(_) => return,
+        };
+        let res = match cluster.disconnect_all(name).await {
+            Ok(()) => cluster.drop_database(name).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            log::warn!("Unable to drop {}: {:?}", name, e.to_string());
+        }
+    }
+
+    /// Renew the lease on a database, fails for the ones we didn't create
+    fn renew(&self, name: &str) -> Result<(), ApiError> {
+        match self.leases.lock().unwrap().renew(name, Instant::now()) {
+            true => Ok(()),
+            false => Err(ApiError::not_found(name)),
+        }
+    }
+}
+
+/// Whether the request carries `token` as its bearer token
+fn authorized(req: &Request<Body>, token: &str) -> bool {
+    let given = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match given {
+        Some(given) => constant_time_eq(given.as_bytes(), token.as_bytes()),
+        None => false,
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Lease {
+    ttl: Duration,
+    expires: Instant,
+    /// The connection whose end drops the database
+    connection: Option<u64>,
+}
+
+/// The databases created through the daemon
+#[derive(Debug, Default)]
+struct Leases(HashMap<String, Lease>);
+
+impl Leases {
+    fn grant(&mut self, name: &str, ttl: Duration, connection: Option<u64>, now: Instant) {
+        let lease = Lease {
+            ttl,
+            expires: now + ttl,
+            connection,
+        };
+        self.0.insert(name.to_string(), lease);
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    fn renew(&mut self, name: &str, now: Instant) -> bool {
+        match self.0.get_mut(name) {
+            Some(lease) => {
+                lease.expires = now + lease.ttl;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn release(&mut self, name: &str) -> bool {
+        self.0.remove(name).is_some()
+    }
+
+    fn release_all(&mut self) -> Vec<String> {
+        self.0.drain().map(|(name, _)| name).collect()
+    }
+
+    fn expired(&mut self, now: Instant) -> Vec<String> {
+        self.release_where(|lease| lease.expires <= now)
+    }
+
+    fn disconnected(&mut self, connection: u64) -> Vec<String> {
+        self.release_where(|lease| lease.connection == Some(connection))
+    }
+
+    fn release_where(&mut self, pred: impl Fn(&Lease) -> bool) -> Vec<String> {
+        let names: Vec<String> = self
+            .0
+            .iter()
+            .filter(|(_, lease)| pred(lease))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in &names {
+            self.0.remove(name);
+        }
+        names
+    }
+
+    fn status(&self, now: Instant) -> Vec<Value> {
+        let mut databases: Vec<Value> = self
+            .0
+            .iter()
+            .map(|(name, lease)| {
+                json!({
+                    "name": name,
+                    "expires_in": lease.expires.saturating_duration_since(now).as_secs(),
+                    "drop_on_disconnect": lease.connection.is_some(),
+                })
+            })
+            .collect();
+        databases.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+        databases
+    }
+}
+
+#[derive(Debug)]
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn not_found(name: &str) -> Self {
+        Self::new(
+            StatusCode::NOT_FOUND,
+            format!("No database {} was created through this daemon", name),
+        )
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e))
+    }
+}
+
+type ApiResult = Result<(StatusCode, Option<Value>), ApiError>;
+
+async fn handle(
+    daemon: Arc<Daemon>,
+    connection: u64,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let started = Instant::now();
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let res = match authorized(&req, &daemon.token) {
+        true => route(&daemon, connection, req).await,
+        false => Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "Missing or wrong bearer token",
+        )),
+    };
+    let (status, body) = match res {
+        Ok(res) => res,
+        Err(e) => (e.status, Some(json!({ "error": e.message }))),
+    };
+    log::info!(
+        "{} {} {} {:?}",
+        method,
+        path,
+        status.as_u16(),
+        started.elapsed()
+    );
+    let response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json");
+    let body = match body {
+        Some(body) => Body::from(body.to_string()),
+        None => Body::empty(),
+    };
+    Ok(response.body(body).expect("The response is valid"))
+}
+
+async fn route(daemon: &Daemon, connection: u64, req: Request<Body>) -> ApiResult {
+    let path = req.uri().path().to_string();
+    let segments: Vec<String> = path
+        .trim_matches('/')
+        .split('/')
+        .map(|s| percent_decode_str(s).decode_utf8_lossy().into_owned())
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    match (req.method().clone(), segments.as_slice()) {
+        (Method::GET, ["status"]) => status(daemon).await,
+        (Method::POST, ["databases"]) => create(daemon, connection, body(req).await?).await,
+        (Method::DELETE, ["databases", name]) => {
+            match daemon.leases.lock().unwrap().release(name) {
+                true => {}
+                false => return Err(ApiError::not_found(name)),
+            }
+            daemon.drop_database(name).await;
+            Ok((StatusCode::NO_CONTENT, None))
+        }
+        (Method::POST, ["databases", name, "migrate"]) => {
+            let database = daemon.database(name).await?;
+            let Migrate { dir } = body(req).await?;
+            database.migrate(dir).await?;
+            Ok((StatusCode::OK, Some(json!({}))))
+        }
+        (Method::POST, ["databases", name, "sql"]) => {
+            let database = daemon.database(name).await?;
+            let Sql { sql } = body(req).await?;
+            let rows = database.run_script(&sql).await?;
+            let columns: Vec<&str> = match rows.first() {
+                Some(row) => row.columns().iter().map(|c| c.name()).collect(),
+                None => vec![],
+            };
+            let values = rows
+                .iter()
+                .map(row_values)
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Ok((
+                StatusCode::OK,
+                Some(json!({ "columns": columns, "rows": values })),
+            ))
+        }
+        (Method::POST, ["databases", name, "lease"]) => {
+            daemon.renew(name)?;
+            Ok((StatusCode::OK, Some(json!({}))))
+        }
+        _ => Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("No route for {} {}", req.method(), path),
+        )),
+    }
+}
+
+/// A JSON body, an empty one stands for `{}`
+async fn body<T: DeserializeOwned>(req: Request<Body>) -> Result<T, ApiError> {
+    let bytes = hyper::body::to_bytes(req.into_body())
+        .await
+        .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.to_string()))?;
+    let bytes: &[u8] = match bytes.is_empty() {
+        true => b"{}",
+        false => &bytes,
+    };
+    serde_json::from_slice(bytes).map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct CreateDatabase {
+    name: Option<String>,
+    /// Copy this database instead of starting from an empty one
+    template: Option<String>,
+    /// A directory of sqlx migrations on the daemon's host
+    migrations: Option<String>,
+    /// Seconds the database lives without its lease being renewed
+    lease: Option<u64>,
+    drop_on_disconnect: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Migrate {
+    dir: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Sql {
+    sql: String,
+}
+
+async fn create(daemon: &Daemon, connection: u64, request: CreateDatabase) -> ApiResult {
+    let name = match request.name.clone() {
+        Some(name) => name,
+        None => cuid::cuid().map_err(anyhow::Error::from)?,
+    };
+    if daemon.leases.lock().unwrap().contains(&name) {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("The database {} already exists", name),
+        ));
+    }
+    let (uri, database) = {
+        let mut cluster = daemon.cluster().await?;
+        let uri = match &request.template {
+            Some(template) => {
+                cluster
+                    .create_database_from_template(Some(&name), template)
+                    .await?
+            }
+            None => cluster.create_database(Some(&name)).await?,
+        };
+        (uri, cluster.database(&name)?)
+    };
+    if let Some(dir) = &request.migrations {
+        if let Err(e) = database.migrate(dir).await {
+            daemon.drop_database(&name).await;
+            return Err(e.into());
+        }
+    }
+
+    let ttl = request
+        .lease
+        .map(Duration::from_secs)
+        .unwrap_or(daemon.lease);
+    let connection = Some(connection).filter(|_| request.drop_on_disconnect);
+    daemon
+        .leases
+        .lock()
+        .unwrap()
+        .grant(&name, ttl, connection, Instant::now());
+    Ok((
+        StatusCode::CREATED,
+        Some(json!({ "name": name, "uri": uri, "lease": ttl.as_secs() })),
+    ))
+}
+
+async fn status(daemon: &Daemon) -> ApiResult {
+    let state = Cluster::find(&daemon.root_path);
+    let uri = daemon.cluster().await?.uri("postgres");
+    let databases = daemon.leases.lock().unwrap().status(Instant::now());
+    Ok((
+        StatusCode::OK,
+        Some(json!({
+            "pid": state.as_ref().map(|s| s.pid),
+            "port": state.as_ref().map(|s| s.port),
+            "uri": uri,
+            "databases": databases,
+        })),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_leases_end_on_expiry_or_disconnect() {
+        let now = Instant::now();
+        let mut leases = Leases::default();
+        leases.grant("a", Duration::from_secs(10), None, now);
+        leases.grant("b", Duration::from_secs(60), Some(1), now);
+        leases.grant("c", Duration::from_secs(60), Some(2), now);
+
+        assert!(leases.renew("a", now + Duration::from_secs(5)));
+        assert!(!leases.renew("unknown", now));
+        assert!(leases.expired(now + Duration::from_secs(10)).is_empty());
+        assert_eq!(leases.expired(now + Duration::from_secs(15)), vec!["a"]);
+
+        assert_eq!(leases.disconnected(1), vec!["b"]);
+        assert!(leases.disconnected(1).is_empty());
+        let status = leases.status(now + Duration::from_secs(20));
+        assert_eq!(status.len(), 1);
+        assert_eq!(status[0]["name"], "c");
+        assert_eq!(status[0]["expires_in"], 40);
+        assert_eq!(status[0]["drop_on_disconnect"], true);
+
+        assert!(leases.release("c"));
+        assert!(!leases.release("c"));
+    }
+
+    #[test]
+    fn test_daemon_requires_the_token() {
+        let request = |auth: Option<&str>| {
+            let mut builder = Request::builder().uri("/status");
+            if let Some(auth) = auth {
+                builder = builder.header(header::AUTHORIZATION, auth);
+            }
+            builder.body(Body::empty()).unwrap()
+        };
+        assert!(authorized(&request(Some("Bearer s3cret")), "s3cret"));
+        assert!(!authorized(&request(Some("Bearer s3cre")), "s3cret"));
+        assert!(!authorized(&request(Some("Bearer wrong!")), "s3cret"));
+        assert!(!authorized(&request(Some("s3cret")), "s3cret"));
+        assert!(!authorized(&request(None), "s3cret"));
+        assert_eq!(generate_token().len(), TOKEN_LENGTH);
+    }
+}
@@ -0,0 +1,243 @@
+//! `pmem`, the embedded clusters from the command line. `start` runs one in
+//! the foreground, the other commands attach to it through the state file in
+//! its root path.
+
+mod args;
+mod daemon;
+mod exec;
+
+use std::{
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use pmem::{Backend, Cluster, Config, PgRow};
+use sqlx::{Row, ValueRef};
+
+use self::args::Args;
+
+const USAGE: &str = "usage: pmem <command> [--option value]...
+
+Commands:
+  start                  Run a cluster in the foreground and print its URI
+  create [name]          Create a database and print its URI
+  drop <name>            Drop a database
+  list                   List the databases
+  migrate <db> <dir>     Run the sqlx migrations in a directory
+  sql <db> <file>        Run a SQL file (`-` for stdin) and print its rows
+  status                 Print the running cluster's pid, port and URI
+  stop                   Stop the running cluster
+  exec [--migrations dir] [--template name] -- <command>...
+                         Run a command with DATABASE_URL and PG* set to a
+                         fresh database, starting a cluster if none is running
+  daemon [--listen addr | --socket path] [--token token] [--lease secs]
+                         Serve a JSON HTTP API creating leased databases
+
+Options are the database configuration fields spelled with dashes, for
+example --root-path (defaults to .pmem), --port, --auth-method or --tls true.";
+
+/// How often `start` checks that its cluster wasn't stopped from elsewhere
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// `pg_ctl status`'s exit code for a server that isn't running
+const NOT_RUNNING: i32 = 3;
+
+#[tokio::main]
+async fn main() {
+    let code = match run(Args::parse(std::env::args().skip(1))).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("pmem: {:#}", e);
+            1
+        }
+    };
+    std::process::exit(code);
+}
+
+async fn run(args: anyhow::Result<Args>) -> anyhow::Result<i32> {
+    let args = args?;
+    let command = match args.command.clone() {
+        None => {
+            println!("{}", USAGE);
+            return Ok(0);
+        }
+        Some(command) => command,
+    };
+    match command.as_str() {
+        "help" => {
+            println!("{}", USAGE);
+            return Ok(0);
+        }
+        "exec" => return exec::exec(args).await,
+        "daemon" => return daemon::daemon(args).await,
+        _ => {}
+    }
+    let config = args.config()?;
+    let _ = pmem::init_logging(&config.log_config());
+    match command.as_str() {
+        "start" => start(config).await?,
+        "create" => {
+            let name = match args.positional.as_slice() {
+                [] => None,
+                [name] => Some(name.as_str()),
+                _ => bail!("usage: pmem create [name]"),
+            };
+            println!("{}", attach(config).await?.create_database(name).await?);
+        }
+        "drop" => {
+            let [name] = args.positional("drop <name>")?;
+            attach(config).await?.drop_database(name).await?;
+        }
+        "list" => {
+            args.positional::<0>("list")?;
+            for name in attach(config).await?.list_databases().await? {
+                println!("{}", name);
+            }
+        }
+        "migrate" => {
+            let [db_name, dir] = args.positional("migrate <db> <dir>")?;
+            attach(config).await?.migrate(db_name, dir).await?;
+        }
+        "sql" => {
+            let [db_name, file] = args.positional("sql <db> <file>")?;
+            let sql = read_script(file)?;
+            for row in attach(config).await?.run_script(db_name, &sql).await? {
+                println!("{}", format_row(&row)?);
+            }
+        }
+        "status" => {
+            args.positional::<0>("status")?;
+            let root_path = root_path(&config);
+            let state = match Cluster::find(&root_path) {
+                Some(state) => state,
+                None => {
+                    println!("not running");
+                    return Ok(NOT_RUNNING);
+                }
+            };
+            let cluster = attach(config).await?;
+            println!("pid: {}", state.pid);
+            println!("port: {}", state.port);
+            println!("uri: {}", cluster.uri("postgres"));
+        }
+        "stop" => {
+            args.positional::<0>("stop")?;
+            let mut cluster = attach(config).await?;
+            cluster.release();
+            cluster.stop().await?;
+        }
+        command => bail!("Unknown command {:?}\n\n{}", command, USAGE),
+    }
+    Ok(0)
+}
+
+fn root_path(config: &Config) -> PathBuf {
+    PathBuf::from(
+        config
+            .root_path
+            .as_deref()
+            .unwrap_or(args::DEFAULT_ROOT_PATH),
+    )
+}
+
+/// Start a cluster, or attach to the one already running, and keep it up
+/// until we're interrupted or it's stopped with `pmem stop`
+async fn start(config: Config) -> anyhow::Result<()> {
+    let root_path = root_path(&config);
+    let mut cluster = Cluster::start(config).await?;
+    println!("{}", cluster.uri("postgres"));
+    let pid = Cluster::find(&root_path)
+        .context("The cluster didn't leave its state behind")?
+        .pid;
+    tokio::select! {
+        _ = shutdown_signal() => {
+            cluster.release();
+            cluster.stop().await?;
+        }
+        _ = exited(pid, &root_path) => {}
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("Unable to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+async fn exited(pid: u32, root_path: &Path) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if Cluster::find(root_path).map(|s| s.pid) != Some(pid) {
+            return;
+        }
+    }
+}
+
+/// The commands other than `start` only work with a running cluster
+async fn attach(config: Config) -> anyhow::Result<Cluster> {
+    let root_path = root_path(&config);
+    if config.db_type != Backend::External && Cluster::find(&root_path).is_none() {
+        bail!(
+            "No cluster is running in {:?}, start one with `pmem start`",
+            root_path
+        );
+    }
+    Cluster::start(config).await
+}
+
+/// Attach to the cluster that's running, or start one. The second value says
+/// whether we attached, a cluster we started is released so it can be stopped
+/// for good.
+async fn start_or_attach(config: Config) -> anyhow::Result<(Cluster, bool)> {
+    let attached =
+        config.db_type == Backend::External || Cluster::find(root_path(&config)).is_some();
+    let mut cluster = Cluster::start(config).await?;
+    if !attached {
+        cluster.release();
+    }
+    Ok((cluster, attached))
+}
+
+fn read_script(file: &str) -> anyhow::Result<String> {
+    if file == "-" {
+        let mut sql = String::new();
+        io::stdin().read_to_string(&mut sql)?;
+        return Ok(sql);
+    }
+    std::fs::read_to_string(file).with_context(|| format!("Unable to read {:?}", file))
+}
+
+/// Tab separated, with NULLs left empty
+fn format_row(row: &PgRow) -> anyhow::Result<String> {
+    let values: Vec<&str> = row_values(row)?
+        .into_iter()
+        .map(|v| v.unwrap_or_default())
+        .collect();
+    Ok(values.join("\t"))
+}
+
+/// The values of a row `run_script` returned, which are all text
+fn row_values(row: &PgRow) -> anyhow::Result<Vec<Option<&str>>> {
+    let mut values = Vec::with_capacity(row.len());
+    for i in 0..row.len() {
+        let value = row.try_get_raw(i)?;
+        values.push(match value.is_null() {
+            true => None,
+            false => Some(value.as_str().map_err(|e| anyhow::anyhow!(e))?),
+        });
+    }
+    Ok(values)
+}
@@ -5,31 +5,170 @@ const {
   start_db,
   stop_db,
   new_db,
+  new_db_uris,
+  pause_replay,
+  resume_replay,
   drop_db,
   execute_sql,
   db_migration,
+  shutdown_all,
+  server_logs,
+  on_server_log,
+  tls_files,
+  on_log: add_log_listener,
+  capture_changes,
+  read_changes,
+  end_capture,
 } = require("./index.node");
 
+// Don't leave postmasters behind when node exits without calling `stop`
+process.once("exit", () => shutdown_all());
+// or when it's interrupted. Once the clusters are down the signal is raised
+// again, to exit the way node would have, unless somebody else handles it.
+for (const signal of ["SIGINT", "SIGTERM"] as const) {
+  process.once(signal, () => {
+    shutdown_all();
+    if (process.listenerCount(signal) === 0) {
+      process.kill(process.pid, signal);
+    }
+  });
+}
+
 export enum DB_TYPE {
   EXTERNAL = "External",
   EMBEDDED = "Embedded",
 }
 
+// Options left out come from pmem.toml and the PMEM_* environment variables,
+// then from the defaults
 export type DatabaseOptions = {
-  db_type: DB_TYPE;
-  uri: string;
+  db_type?: DB_TYPE;
+  uri?: string;
   root_path?: string;
   username?: string;
   password?: string;
   persistent?: boolean;
   port?: number;
   timeout?: number;
+  // How long to wait between attempts to reach an external server that
+  // isn't up yet, in milliseconds, doubling up to the max until `timeout`
+  retry_interval?: number;
+  max_retry_interval?: number;
   host?: string;
+  reuse?: boolean;
+  auth_method?: "trust" | "password" | "md5" | "scram-sha-256";
+  generate_password?: boolean;
+  tls?: boolean;
+  client_certificates?: boolean;
+  replica?: boolean;
+  logical_decoding?: boolean;
+  in_memory?: boolean;
+  memory_path?: string;
+  keep_on_failure?: boolean;
+  // Hand out URIs for a login role created with each database, owning it or
+  // with these table privileges, e.g. "SELECT, INSERT, UPDATE, DELETE"
+  app_role?: boolean;
+  app_role_grants?: string;
+  // pmem's own logs: a level (`off` disables them), a directory for log files
+  // (stderr only without one) and their format
+  log_level?: string;
+  log_dir?: string;
+  log_format?: "text" | "json";
+};
+
+export type LogRecord = {
+  time: number;
+  level: string;
+  target: string;
+  message: string;
+};
+
+// Forward pmem's log records, e.g. to a test reporter. Logging is set up once
+// per process by the first database to start, with its options.
+export function on_log(callback: (record: LogRecord) => void) {
+  add_log_listener(callback);
+}
+
+export type DatabaseUris = {
+  primary: string;
+  replica?: string;
+};
+
+export type CreateOptions = {
+  owner?: string;
+  // Copied instead of template1, template0 when an encoding or a locale is set
+  template?: string;
+  encoding?: string;
+  lc_collate?: string;
+  lc_ctype?: string;
+  // Use ICU collations with this locale, postgres 15 and later (not the embedded 14.3)
+  icu_locale?: string;
+  connection_limit?: number;
+  // Applied with ALTER DATABASE ... SET
+  settings?: Record<string, string | number | boolean>;
+};
+
+export type StopMode = "smart" | "fast" | "immediate" | "kill";
+
+export type StopOptions = {
+  // Where to start, slower modes escalate once their share of the timeout is up
+  mode?: Exclude<StopMode, "kill">;
+  // Seconds until the server is killed
+  timeout?: number;
+};
+
+export type TlsFiles = {
+  ca_cert: string;
+  server_cert: string;
+  server_key: string;
+  client_cert?: string;
+  client_key?: string;
+};
+
+export type RowChange = {
+  kind: "insert" | "update" | "delete" | "truncate";
+  schema: string;
+  table: string;
+  new?: Record<string, any>;
+  old?: Record<string, any>;
+};
+
+export class ChangeCapture {
+  db: any;
+  id: string;
+
+  constructor(db: any, id: string) {
+    this.db = db;
+    this.id = id;
+  }
+
+  // The rows changed by transactions committed since the last call
+  async changes(): Promise<RowChange[]> {
+    return read_changes.call(this.db, this.id);
+  }
+
+  async end() {
+    return end_capture.call(this.db, this.id);
+  }
+}
+
+export type ServerLogFilter = {
+  since?: Date | number;
+  level?: string;
+  database?: string;
+};
+
+export type ServerLogEntry = {
+  time: number;
+  timestamp: string;
+  pid: number;
+  database?: string;
+  level: string;
+  message: string;
 };
 
 const default_options: DatabaseOptions = {
   db_type: DB_TYPE.EMBEDDED,
-  uri: "127.0.0.1",
   username: "postgres",
   password: "postgres",
   timeout: 1000,
@@ -40,8 +179,8 @@ export class Database {
   options: DatabaseOptions;
   used = false;
 
-  constructor(options: DatabaseOptions) {
-    this.options = { ...default_options, ...options };
+  constructor(options: DatabaseOptions = {}) {
+    this.options = options;
   }
 
   async start() {
@@ -49,18 +188,51 @@ export class Database {
     return db && start_db.call(db);
   }
 
-  async stop() {
+  // Resolves to the mode that stopped the server, null when there was nothing
+  // to stop. Rejects when the server couldn't be stopped.
+  async stop(options: StopOptions = {}): Promise<StopMode | null> {
+    let res = null;
     if (this.db) {
-      const res = await stop_db.call(this.db);
-      this.used = true;
-      this.db = undefined;
+      try {
+        res = await stop_db.call(this.db, options);
+      } finally {
+        // The handle is closed either way
+        this.used = true;
+        this.db = undefined;
+      }
     }
-    return this;
+    return res;
+  }
+
+  async new_db(name?: string, options?: CreateOptions): Promise<string> {
+    let db = await this._get_db();
+    return db && new_db.call(db, name, options);
+  }
+
+  // The new database's URIs on the primary and, with `replica: true`, on the replica
+  async new_db_uris(name?: string, options?: CreateOptions): Promise<DatabaseUris> {
+    let db = await this._get_db();
+    return db && new_db_uris.call(db, name, options);
+  }
+
+  // Stop applying WAL on the replica, so it lags behind the primary
+  async pause_replay() {
+    let db = await this._get_db();
+    return db && pause_replay.call(db);
   }
 
-  async new_db(name?: string) {
+  // Apply WAL again, resolves once the replica has caught up
+  async resume_replay() {
     let db = await this._get_db();
-    return db && new_db.call(db, name);
+    return db && resume_replay.call(db);
+  }
+
+  // Needs `logical_decoding: true`
+  async capture_changes(uri: string): Promise<ChangeCapture> {
+    let db = await this._get_db();
+    let db_name = this._get_db_name_from_uri(uri);
+    let id = await capture_changes.call(db, db_name);
+    return new ChangeCapture(db, id);
   }
 
   async run_migrations(uri: string, migrations_dir: string) {
@@ -77,22 +249,40 @@ export class Database {
   async drop_db(uri: string) {
     if (this.db) {
       const url_obj = new URL(uri);
-      const db_name = url_obj.pathname.split("/")[1];
+      const db_name = this._get_db_name_from_uri(uri);
       const parsed_uri = url.format(url_obj, { search: false });
       await drop_db.call(this.db, parsed_uri, db_name);
     }
   }
 
+  async server_logs(filter: ServerLogFilter = {}): Promise<ServerLogEntry[]> {
+    let db = await this._get_db();
+    const since =
+      filter.since instanceof Date ? filter.since.getTime() : filter.since;
+    return db && server_logs.call(db, { ...filter, since });
+  }
+
+  async on_server_log(callback: (entry: ServerLogEntry) => void) {
+    let db = await this._get_db();
+    return db && on_server_log.call(db, callback);
+  }
+
+  async tls_files(): Promise<TlsFiles | null> {
+    let db = await this._get_db();
+    return db && tls_files.call(db);
+  }
+
   async _get_db() {
     if (!this.db) {
-      this.db = init_db(this.options);
+      this.db = init_db(this.options, default_options);
     }
     return this.db;
   }
 
   _get_db_name_from_uri(uri: string) {
     const url_obj = new URL(uri);
-    const db_name = url_obj.pathname.split("/")[1];
+    // Names with spaces and the like come percent-encoded
+    const db_name = decodeURIComponent(url_obj.pathname.split("/")[1]);
     return db_name;
   }
 }
@@ -1,11 +1,41 @@
+//! Throwaway postgres databases for tests, embedded or on an external server.
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! let config = pmem::Config::builder().generate_password(true).build();
+//! let mut cluster = pmem::Cluster::start(config).await?;
+//! let uri = cluster.create_database(None).await?;
+//! println!("created {}", uri);
+//! cluster.stop().await?;
+//! # Ok(())
+//! # }
+//! ```
 extern crate serde_derive;
 
 extern crate tempdir;
+// Lets `#[pmem::test]` expand the same way inside this crate
+extern crate self as pmem;
+#[cfg(feature = "node")]
 use neon::prelude::*;
 
+#[cfg(feature = "node")]
 use crate::system::neon_main;
 pub(crate) mod system;
 
+pub use pmem_macros::test;
+pub use sqlx::postgres::PgRow;
+
+#[doc(hidden)]
+pub use crate::system::testing as __testing;
+
+pub use crate::system::{
+    add_listener, init_logging, stop_all, AuthMethod, Backend, ChangeKind, Cluster, ClusterState,
+    ConfigBuilder, ConfigDatabase as Config, ConfigError, CreateOptions, Database, DatabaseUris,
+    LogConfig, LogFormat, LogRecord, RowChange, ServerLogEntry, ServerLogFilter, StopMode,
+    StopOptions, TlsFiles,
+};
+
+#[cfg(feature = "node")]
 #[neon::main]
 pub fn main(cx: ModuleContext) -> NeonResult<()> {
     neon_main(cx)
@@ -0,0 +1,96 @@
+use pg_embed::pg_enums::PgAuthMethod;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+
+const GENERATED_PASSWORD_LENGTH: usize = 32;
+
+/// How clients authenticate against the embedded cluster
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub enum AuthMethod {
+    #[serde(rename = "trust")]
+    Trust,
+    #[default]
+    #[serde(rename = "password")]
+    Password,
+    #[serde(rename = "md5")]
+    Md5,
+    #[serde(rename = "scram-sha-256")]
+    ScramSha256,
+}
+
+impl AuthMethod {
+    /// The method as `pg_hba.conf` spells it
+    pub fn as_hba(&self) -> &'static str {
+        match self {
+            AuthMethod::Trust => "trust",
+            AuthMethod::Password => "password",
+            AuthMethod::Md5 => "md5",
+            AuthMethod::ScramSha256 => "scram-sha-256",
+        }
+    }
+
+    /// What initdb gets, it doesn't know about trust so we start out with
+    /// passwords and loosen `pg_hba.conf` afterwards
+    pub fn to_pg_embed(self) -> PgAuthMethod {
+        match self {
+            AuthMethod::Trust | AuthMethod::Password => PgAuthMethod::Plain,
+            AuthMethod::Md5 => PgAuthMethod::MD5,
+            AuthMethod::ScramSha256 => PgAuthMethod::ScramSha256,
+        }
+    }
+
+    /// How the server hashes passwords, md5 auth can only verify md5 hashes
+    pub fn password_encryption(&self) -> &'static str {
+        match self {
+            AuthMethod::Md5 => "md5",
+            _ => "scram-sha-256",
+        }
+    }
+
+    pub fn needs_password(&self) -> bool {
+        *self != AuthMethod::Trust
+    }
+}
+
+pub fn generate_password() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(GENERATED_PASSWORD_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct TestStruct {
+        auth_method: AuthMethod,
+    }
+
+    #[test]
+    fn test_auth_method_deserializes_hba_names() {
+        crate::serde_json_eq!(
+            TestStruct,
+            "{\"auth_method\":\"scram-sha-256\"}",
+            auth_method,
+            AuthMethod::ScramSha256
+        );
+        crate::serde_json_eq!(
+            TestStruct,
+            "{\"auth_method\":\"trust\"}",
+            auth_method,
+            AuthMethod::Trust
+        );
+        assert!(serde_json::from_str::<TestStruct>("{\"auth_method\":\"peer\"}").is_err());
+    }
+
+    #[test]
+    fn test_generated_passwords_differ() {
+        let password = generate_password();
+        assert_eq!(password.len(), GENERATED_PASSWORD_LENGTH);
+        assert!(password.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(password, generate_password());
+    }
+}
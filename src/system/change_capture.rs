@@ -0,0 +1,296 @@
+use anyhow::bail;
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
+use sqlx::{Connection, PgConnection, Row};
+
+use super::identifier;
+
+const PLUGIN: &str = "test_decoding";
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Insert,
+    Update,
+    Delete,
+    Truncate,
+}
+
+/// One row changed by a committed transaction
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RowChange {
+    pub kind: ChangeKind,
+    pub schema: String,
+    pub table: String,
+    /// The row after an insert or update
+    pub new: Option<Map<String, Value>>,
+    /// The key of an updated row whose key changed (or the whole row with
+    /// `REPLICA IDENTITY FULL`), and the key of a deleted row
+    pub old: Option<Map<String, Value>>,
+}
+
+/// A temporary logical replication slot on one database. The slot belongs to
+/// the connection held here, so the server drops it as soon as that
+/// connection goes away, however the session ends.
+#[derive(Debug)]
+pub struct ChangeCapture {
+    pub slot_name: String,
+    pub database: String,
+    conn: PgConnection,
+}
+
+impl ChangeCapture {
+    /// Start recording changes made to the database `uri` points at. Only
+    /// transactions committed from here on are returned.
+    pub async fn start(uri: &str, database: &str) -> anyhow::Result<Self> {
+        let slot_name = format!("pmem_{}", cuid::cuid()?);
+        let mut conn = PgConnection::connect_with(&identifier::connect_options(uri)?).await?;
+        let res = sqlx::query("SELECT pg_create_logical_replication_slot($1, $2, true)")
+            .bind(&slot_name)
+            .bind(PLUGIN)
+            .execute(&mut conn)
+            .await;
+        if let Err(e) = res {
+            bail!(
+                "Unable to create a replication slot, change capture needs `logical_decoding` enabled: {}",
+                e
+            );
+        }
+        log::info!("Capturing changes on {} with slot {}", database, slot_name);
+        Ok(Self {
+            slot_name,
+            database: database.to_string(),
+            conn,
+        })
+    }
+
+    /// Every change committed since the previous call (or the start)
+    pub async fn changes(&mut self) -> anyhow::Result<Vec<RowChange>> {
+        let rows = sqlx::query(
+            "SELECT data FROM pg_logical_slot_get_changes($1, NULL, NULL, 'include-xids', '0')",
+        )
+        .bind(&self.slot_name)
+        .fetch_all(&mut self.conn)
+        .await?;
+
+        let mut changes = vec![];
+        for row in rows {
+            let data: String = row.try_get("data")?;
+            if let Some(change) = parse_change(&data)? {
+                changes.push(change);
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Close the session's connection, which takes the slot with it
+    pub async fn end(self) -> anyhow::Result<()> {
+        log::info!("Ending change capture {}", self.slot_name);
+        self.conn.close().await?;
+        Ok(())
+    }
+}
+
+/// Parse one line of `test_decoding` output. Transaction boundaries and
+/// logical messages aren't row changes and come back as `None`.
+pub fn parse_change(line: &str) -> anyhow::Result<Option<RowChange>> {
+    let rest = match line.strip_prefix("table ") {
+        Some(rest) => rest,
+        None => return Ok(None),
+    };
+
+    let (schema, rest) = parse_ident(rest)?;
+    let rest = expect(rest, ".")?;
+    let (table, rest) = parse_ident(rest)?;
+    let rest = expect(rest, ": ")?;
+    let (kind, rest) = rest
+        .split_once(": ")
+        .ok_or_else(|| anyhow::anyhow!("Missing change kind in {:?}", line))?;
+
+    let (kind, new, old) = match kind {
+        "INSERT" => (ChangeKind::Insert, parse_tuple(rest)?.0, None),
+        "UPDATE" => match rest.strip_prefix("old-key: ") {
+            Some(rest) => {
+                let (old, rest) = parse_tuple(rest)?;
+                let rest = expect(rest, "new-tuple: ")?;
+                (ChangeKind::Update, parse_tuple(rest)?.0, old)
+            }
+            None => (ChangeKind::Update, parse_tuple(rest)?.0, None),
+        },
+        "DELETE" => (ChangeKind::Delete, None, parse_tuple(rest)?.0),
+        "TRUNCATE" => (ChangeKind::Truncate, None, None),
+        other => bail!("Unknown change kind {:?}", other),
+    };
+
+    Ok(Some(RowChange {
+        kind,
+        schema,
+        table,
+        new,
+        old,
+    }))
+}
+
+fn expect<'a>(s: &'a str, prefix: &str) -> anyhow::Result<&'a str> {
+    s.strip_prefix(prefix)
+        .ok_or_else(|| anyhow::anyhow!("Expected {:?} at {:?}", prefix, s))
+}
+
+/// An identifier, double quoted when postgres thought it had to be
+fn parse_ident(s: &str) -> anyhow::Result<(String, &str)> {
+    match s.strip_prefix('"') {
+        Some(rest) => {
+            let (ident, rest) = parse_quoted(rest, '"')?;
+            Ok((ident, rest))
+        }
+        None => {
+            let end = s.find(['.', '[', ':', ' ']).unwrap_or(s.len());
+            Ok((s[..end].to_string(), &s[end..]))
+        }
+    }
+}
+
+/// The rest of a quoted string after its opening quote, doubled quotes
+/// standing for one
+fn parse_quoted(s: &str, quote: char) -> anyhow::Result<(String, &str)> {
+    let mut out = String::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != quote {
+            out.push(c);
+        } else if matches!(chars.peek(), Some((_, next)) if *next == quote) {
+            out.push(quote);
+            chars.next();
+        } else {
+            return Ok((out, &s[i + c.len_utf8()..]));
+        }
+    }
+    bail!("Unterminated quote in {:?}", s)
+}
+
+/// Columns as `name[type]:value`, separated by spaces. Stops at the end or
+/// at the `new-tuple:` that follows an update's old key.
+fn parse_tuple(s: &str) -> anyhow::Result<(Option<Map<String, Value>>, &str)> {
+    if let Some(rest) = s.strip_prefix("(no-tuple-data)") {
+        return Ok((None, rest.trim_start()));
+    }
+
+    let mut columns = Map::new();
+    let mut rest = s;
+    while !rest.is_empty() && !rest.starts_with("new-tuple: ") {
+        let (name, after_name) = parse_ident(rest)?;
+        let after_name = expect(after_name, "[")?;
+        // Array types have brackets of their own, `tags[text[]]:'{a}'`
+        let type_end = after_name
+            .find("]:")
+            .ok_or_else(|| anyhow::anyhow!("Missing type for column {:?}", name))?;
+        let type_name = &after_name[..type_end];
+        let after_type = &after_name[type_end + 2..];
+
+        let (value, after_value) = match after_type.strip_prefix('\'') {
+            Some(quoted) => {
+                let (value, after) = parse_quoted(quoted, '\'')?;
+                (Value::String(value), after)
+            }
+            None => {
+                let end = after_type.find(' ').unwrap_or(after_type.len());
+                (
+                    unquoted_value(type_name, &after_type[..end]),
+                    &after_type[end..],
+                )
+            }
+        };
+        columns.insert(name, value);
+        rest = after_value.trim_start_matches(' ');
+    }
+    Ok((Some(columns), rest))
+}
+
+/// test_decoding only leaves numbers, booleans and a couple of markers
+/// unquoted
+fn unquoted_value(type_name: &str, raw: &str) -> Value {
+    if raw == "null" {
+        return Value::Null;
+    }
+    match type_name {
+        "boolean" => Value::Bool(raw == "true"),
+        "smallint" | "integer" | "bigint" | "oid" => raw
+            .parse::<i64>()
+            .map(|n| Value::Number(n.into()))
+            .unwrap_or_else(|_| Value::String(raw.to_string())),
+        "real" | "double precision" | "numeric" => raw
+            .parse::<f64>()
+            .ok()
+            .and_then(Number::from_f64)
+            .map(Value::Number)
+            .unwrap_or_else(|| Value::String(raw.to_string())),
+        _ => Value::String(raw.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn parse(line: &str) -> RowChange {
+        parse_change(line).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_parse_change_reads_inserts_with_every_kind_of_value() {
+        let change = parse(
+            "table public.items: INSERT: id[integer]:1 name[text]:'it''s here' \
+             price[numeric]:9.5 active[boolean]:true tags[text[]]:'{a,b}' \
+             note[character varying]:null",
+        );
+        assert_eq!(change.kind, ChangeKind::Insert);
+        assert_eq!(change.schema, "public");
+        assert_eq!(change.table, "items");
+        assert_eq!(
+            Value::Object(change.new.unwrap()),
+            json!({
+                "id": 1,
+                "name": "it's here",
+                "price": 9.5,
+                "active": true,
+                "tags": "{a,b}",
+                "note": null,
+            })
+        );
+        assert_eq!(change.old, None);
+    }
+
+    #[test]
+    fn test_parse_change_reads_updates_deletes_and_quoted_names() {
+        let change = parse(
+            "table public.\"Line Items\": UPDATE: old-key: id[integer]:1 new-tuple: id[integer]:2 \"Qty\"[integer]:3",
+        );
+        assert_eq!(change.kind, ChangeKind::Update);
+        assert_eq!(change.table, "Line Items");
+        assert_eq!(Value::Object(change.old.unwrap()), json!({"id": 1}));
+        assert_eq!(
+            Value::Object(change.new.unwrap()),
+            json!({"id": 2, "Qty": 3})
+        );
+
+        let change = parse("table public.items: DELETE: id[integer]:2");
+        assert_eq!(change.kind, ChangeKind::Delete);
+        assert_eq!(Value::Object(change.old.unwrap()), json!({"id": 2}));
+
+        let change = parse("table public.items: DELETE: (no-tuple-data)");
+        assert_eq!(change.old, None);
+
+        let change = parse("table public.items: TRUNCATE: (no-flags)");
+        assert_eq!(change.kind, ChangeKind::Truncate);
+    }
+
+    #[test]
+    fn test_parse_change_skips_everything_but_rows() {
+        assert_eq!(parse_change("BEGIN").unwrap(), None);
+        assert_eq!(parse_change("COMMIT").unwrap(), None);
+        assert_eq!(parse_change("message: transactional: 1").unwrap(), None);
+        assert!(parse_change("table public.items: INSERT: id[integer").is_err());
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    fmt,
+    future::Future,
+    path::{Path, PathBuf},
+};
+
+use sqlx::{postgres::PgRow, Executor};
+
+use super::{
+    change_capture::RowChange,
+    config::ConfigDatabase,
+    create_options::CreateOptions,
+    db,
+    replica::DatabaseUris,
+    server_log::{self, ServerLogEntry, ServerLogFilter},
+    shutdown::{StopMode, StopOptions},
+    state::{self, ClusterState},
+    system::System,
+    tls::TlsFiles,
+};
+
+/// A postgres server to create throwaway databases on, driving the same
+/// `System` the Node bindings do. An embedded cluster is stopped when this
+/// is dropped, if `stop` wasn't called first.
+#[derive(Debug)]
+pub struct Cluster {
+    system: System,
+}
+
+impl Cluster {
+    /// Start the embedded cluster `config` describes, or use the external
+    /// server it points at
+    pub async fn start(config: ConfigDatabase) -> anyhow::Result<Self> {
+        let mut system = System::initialize(config).await?;
+        system.start().await?;
+        Ok(Self { system })
+    }
+
+    /// The reusable cluster left running in `root_path`, if there is one
+    pub fn find(root_path: impl AsRef<Path>) -> Option<ClusterState> {
+        ClusterState::read(root_path.as_ref()).filter(|s| state::is_postgres_running(s.pid))
+    }
+
+    /// Create a database, named by us unless `name` is given, and return
+    /// its URI
+    pub async fn create_database(&mut self, name: Option<&str>) -> anyhow::Result<String> {
+        self.create_database_with(name, &CreateOptions::default())
+            .await
+    }
+
+    /// Like `create_database`, with an owner, an encoding, locales, a
+    /// template, a connection limit or settings
+    pub async fn create_database_with(
+        &mut self,
+        name: Option<&str>,
+        options: &CreateOptions,
+    ) -> anyhow::Result<String> {
+        self.system
+            .create_new_db(name.map(String::from), options)
+            .await
+    }
+
+    /// Like `create_database_with`, with the URI on the replica as well
+    pub async fn create_database_uris(
+        &mut self,
+        name: Option<&str>,
+        options: &CreateOptions,
+    ) -> anyhow::Result<DatabaseUris> {
+        self.system
+            .create_new_db_uris(name.map(String::from), options)
+            .await
+    }
+
+    /// Create a database as a copy of `template`, which can't have any other
+    /// sessions while that happens
+    pub async fn create_database_from_template(
+        &mut self,
+        name: Option<&str>,
+        template: &str,
+    ) -> anyhow::Result<String> {
+        self.system
+            .create_new_db_from_template(name.map(String::from), template.to_string())
+            .await
+    }
+
+    /// Terminate the sessions connected to a database
+    pub async fn disconnect_all(&mut self, db_name: &str) -> anyhow::Result<()> {
+        self.system.kill_all_connections(db_name.to_string()).await
+    }
+
+    pub async fn drop_database(&mut self, name: &str) -> anyhow::Result<()> {
+        self.system.drop_database(name.to_string()).await
+    }
+
+    /// The databases on the server, leaving out `postgres` and the templates
+    pub async fn list_databases(&mut self) -> anyhow::Result<Vec<String>> {
+        self.system.list_databases().await
+    }
+
+    /// Run the sqlx migrations in `migrations_dir` against a database
+    pub async fn migrate(
+        &self,
+        db_name: &str,
+        migrations_dir: impl AsRef<Path>,
+    ) -> anyhow::Result<()> {
+        let path = migrations_dir.as_ref().to_string_lossy().into_owned();
+        self.system.migration(db_name.to_string(), path).await
+    }
+
+    pub async fn query(&self, db_name: &str, sql: &str) -> anyhow::Result<Vec<PgRow>> {
+        self.system
+            .execute_sql(sql.to_string(), Some(db_name.to_string()))
+            .await
+    }
+
+    /// Run a script of one or more statements. Unlike `query`'s, the rows
+    /// come back in text format.
+    pub async fn run_script(&self, db_name: &str, sql: &str) -> anyhow::Result<Vec<PgRow>> {
+        self.system
+            .execute_script(sql.to_string(), Some(db_name.to_string()))
+            .await
+    }
+
+    /// The URI of a database on this server
+    pub fn uri(&self, db_name: &str) -> String {
+        self.system.full_db_uri(db_name)
+    }
+
+    /// A handle on a database that doesn't borrow the cluster, for running
+    /// migrations and SQL while it's busy with something else
+    pub fn database(&self, db_name: &str) -> anyhow::Result<Database> {
+        Ok(Database {
+            name: db_name.to_string(),
+            uri: self.system.admin_db_uri(db_name)?,
+        })
+    }
+
+    pub fn tls_files(&self) -> Option<TlsFiles> {
+        self.system.tls_files()
+    }
+
+    /// The server's version, an external server's as it reported it when
+    /// it started
+    pub fn server_version(&self) -> Option<&str> {
+        self.system.server_version()
+    }
+
+    pub fn server_logs(&self, filter: &ServerLogFilter) -> anyhow::Result<Vec<ServerLogEntry>> {
+        self.system.server_logs(filter)
+    }
+
+    pub fn server_log_path(&self) -> anyhow::Result<PathBuf> {
+        self.system.server_log_path()
+    }
+
+    /// Hand every entry the server logs from now on to `on_entry`, for as
+    /// long as the returned future is polled
+    pub fn follow_server_logs(
+        &self,
+        on_entry: impl FnMut(ServerLogEntry),
+    ) -> anyhow::Result<impl Future<Output = ()>> {
+        Ok(server_log::follow(
+            self.system.server_log_path()?,
+            || true,
+            on_entry,
+        ))
+    }
+
+    pub async fn pause_replay(&mut self) -> anyhow::Result<()> {
+        self.system.pause_replay().await
+    }
+
+    pub async fn resume_replay(&mut self) -> anyhow::Result<()> {
+        self.system.resume_replay().await
+    }
+
+    /// Start recording the rows changed in a database, returns the session's
+    /// id for `read_changes`
+    pub async fn start_change_capture(&mut self, db_name: &str) -> anyhow::Result<String> {
+        self.system.start_change_capture(db_name.to_string()).await
+    }
+
+    pub async fn read_changes(&mut self, id: &str) -> anyhow::Result<Vec<RowChange>> {
+        self.system.read_changes(id.to_string()).await
+    }
+
+    pub async fn end_change_capture(&mut self, id: &str) -> anyhow::Result<()> {
+        self.system.end_change_capture(id.to_string()).await
+    }
+
+    /// Stop a reusable cluster for good instead of leaving it running for
+    /// the next run
+    pub fn release(&mut self) {
+        self.system.release();
+    }
+
+    /// Keep the workspace around once the process exits, when the cluster
+    /// was configured to `keep_on_failure`
+    pub(crate) fn mark_failed(&mut self) {
+        self.system.mark_failed();
+    }
+
+    pub async fn stop(self) -> anyhow::Result<Option<StopMode>> {
+        self.stop_with(&StopOptions::default()).await
+    }
+
+    /// Stop the server, escalating as `options` allow. Returns the mode that
+    /// stopped it, or `None` when there was nothing for us to stop.
+    pub async fn stop_with(mut self, options: &StopOptions) -> anyhow::Result<Option<StopMode>> {
+        self.system.stop(options).await
+    }
+}
+
+/// A database on a `Cluster`, every call opens a connection of its own as
+/// the admin user
+#[derive(Clone)]
+pub struct Database {
+    name: String,
+    uri: String,
+}
+
+impl Database {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Like `Cluster::migrate`
+    pub async fn migrate(&self, migrations_dir: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = migrations_dir.as_ref().to_string_lossy();
+        let mut conn = db::connect(&self.uri).await?;
+        db::run_migrations(&mut conn, &path).await
+    }
+
+    /// Like `Cluster::query`
+    pub async fn query(&self, sql: &str) -> anyhow::Result<Vec<PgRow>> {
+        let mut conn = db::connect(&self.uri).await?;
+        Ok(conn.fetch_all(sqlx::query(sql)).await?)
+    }
+
+    /// Like `Cluster::run_script`
+    pub async fn run_script(&self, sql: &str) -> anyhow::Result<Vec<PgRow>> {
+        let mut conn = db::connect(&self.uri).await?;
+        Ok(conn.fetch_all(sql).await?)
+    }
+}
+
+// The URI holds the password
+impl fmt::Debug for Database {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Database")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sqlx::Row;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cluster_runs_migrations_and_queries() {
+        let config = ConfigDatabase::builder().generate_password(true).build();
+        let mut cluster = Cluster::start(config).await.unwrap();
+
+        let uri = cluster.create_database(Some("app")).await.unwrap();
+        assert_eq!(uri, cluster.uri("app"));
+        cluster
+            .migrate("app", "test/fixtures/migrations")
+            .await
+            .unwrap();
+        let rows = cluster
+            .query("app", "SELECT email FROM public.\"User\"")
+            .await
+            .unwrap();
+        let email: String = rows[0].get(0);
+        assert_eq!(email, "demo@company.com");
+
+        // A handle doesn't keep the cluster borrowed
+        let app = cluster.database("app").unwrap();
+        let created = cluster.create_database(Some("other")).await.unwrap();
+        assert_eq!(created, cluster.uri("other"));
+        let rows = app
+            .query("SELECT count(*) FROM public.\"User\"")
+            .await
+            .unwrap();
+        assert_eq!(rows[0].get::<i64, _>(0), 1);
+        app.run_script("DELETE FROM public.\"User\"; DELETE FROM public.\"User\"")
+            .await
+            .unwrap();
+        assert_eq!(format!("{:?}", app), "Database { name: \"app\", .. }");
+        assert!(cluster.database("a'b").is_err());
+        cluster.drop_database("other").await.unwrap();
+
+        cluster.drop_database("app").await.unwrap();
+        assert!(cluster.query("app", "SELECT 1").await.is_err());
+        assert_eq!(cluster.stop().await.unwrap(), Some(StopMode::Fast));
+    }
+}
@@ -1,64 +1,548 @@
-use std::time::Duration;
-use tempdir::TempDir;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
 
-use super::{db::DBType, utils::deserialize_optional_datetime_from_sec};
+use super::{
+    app_role::AppRole,
+    auth::AuthMethod,
+    config_file,
+    external::ExternalServer,
+    logger::{LogConfig, LogFormat, DEFAULT_LOG_LEVEL},
+    memory::DEFAULT_MEMORY_PATH,
+    redact::redact_uri,
+    utils::{deserialize_optional_datetime_from_sec, deserialize_optional_duration_from_millis},
+};
+
+/// Where the databases live, the `db_type` option
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Backend {
+    /// A cluster pmem runs itself
+    #[default]
+    #[serde(alias = "embedded")]
+    Embedded,
+    /// The server at `uri`
+    #[serde(alias = "external")]
+    External,
+}
+
+/// Why a configuration can't be used. The field is spelled the way it was
+/// given.
+#[derive(Debug, Error, PartialEq)]
+pub enum ConfigError {
+    #[error("{0}: unknown option")]
+    Unknown(String),
+    #[error("{field}: expected {expected}, got {value}")]
+    Invalid {
+        field: String,
+        expected: &'static str,
+        value: String,
+    },
+    #[error("{field}: required {when}")]
+    Missing {
+        field: &'static str,
+        when: &'static str,
+    },
+    #[error("{field}: can't be combined with {other}")]
+    Conflict {
+        field: &'static str,
+        other: &'static str,
+    },
+    #[error("{field}: only supported on {platform}")]
+    Unsupported {
+        field: &'static str,
+        platform: &'static str,
+    },
+}
+
+const GRANTS_EXPECTED: &str = "a comma separated list of table privileges";
+
+/// Embedded clusters can't take more, pg-embed keeps the port in an `i16`
+const MAX_EMBEDDED_PORT: u16 = i16::MAX as u16;
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
-#[serde(default)]
+#[serde(default, deny_unknown_fields)]
 pub struct ConfigDatabase {
-    pub db_type: String,
+    #[serde(alias = "dbType")]
+    pub db_type: Backend,
     pub uri: String,
+    #[serde(alias = "rootPath")]
     pub root_path: Option<String>,
     pub username: Option<String>,
     pub password: Option<String>,
     pub persistent: Option<bool>,
-    pub port: Option<i16>,
+    pub port: Option<u16>,
     #[serde(
         skip_serializing_if = "Option::is_none",
         deserialize_with = "deserialize_optional_datetime_from_sec"
     )]
     pub timeout: Option<Duration>,
+    /// How long to wait before trying an external server that isn't
+    /// accepting connections again, in milliseconds. It doubles after each
+    /// attempt, up to `max_retry_interval`, until `timeout` is up.
+    #[serde(
+        alias = "retryInterval",
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_optional_duration_from_millis"
+    )]
+    pub retry_interval: Option<Duration>,
+    #[serde(
+        alias = "maxRetryInterval",
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_optional_duration_from_millis"
+    )]
+    pub max_retry_interval: Option<Duration>,
     pub host: Option<String>,
+    /// Leave the embedded cluster running between runs and attach to it
+    /// through the state file in `root_path` instead of starting a new one
+    pub reuse: Option<bool>,
+    /// How clients authenticate against the embedded cluster
+    #[serde(alias = "authMethod")]
+    pub auth_method: Option<AuthMethod>,
+    /// Ignore `password` and use a random one for the cluster, it ends up in
+    /// the connection URIs
+    #[serde(alias = "generatePassword")]
+    pub generate_password: Option<bool>,
+    /// Only accept TLS connections, with certificates pmem generates
+    pub tls: Option<bool>,
+    /// Also require clients to present a certificate, implies `tls`
+    #[serde(alias = "clientCertificates")]
+    pub client_certificates: Option<bool>,
+    /// Start a streaming replica of the embedded cluster next to it
+    pub replica: Option<bool>,
+    /// Run the embedded cluster with `wal_level = logical` so row changes
+    /// can be captured
+    #[serde(alias = "logicalDecoding")]
+    pub logical_decoding: Option<bool>,
+    /// Keep the embedded cluster's data on a tmpfs instead of under
+    /// `root_path`, it's removed when the cluster stops
+    #[serde(alias = "inMemory")]
+    pub in_memory: Option<bool>,
+    /// The tmpfs to use for `in_memory`
+    #[serde(alias = "memoryPath")]
+    pub memory_path: Option<String>,
+    /// Leave a temporary `root_path` behind when the cluster fails to start
+    /// or stop, to look at its logs and data
+    #[serde(alias = "keepOnFailure")]
+    pub keep_on_failure: Option<bool>,
+    /// Create a login role with each database and hand out URIs that use
+    /// it instead of the admin user
+    #[serde(alias = "appRole")]
+    pub app_role: Option<bool>,
+    /// The table privileges the role gets instead of owning the database,
+    /// e.g. `SELECT, INSERT, UPDATE, DELETE`, implies `app_role`
+    #[serde(alias = "appRoleGrants")]
+    pub app_role_grants: Option<String>,
+    /// pmem's own log level, `off` disables logging
+    #[serde(alias = "logLevel")]
+    pub log_level: Option<String>,
+    /// Write pmem's logs to files in this directory instead of only stderr
+    #[serde(alias = "logDir")]
+    pub log_dir: Option<String>,
+    #[serde(alias = "logFormat")]
+    pub log_format: Option<LogFormat>,
 }
 
-impl Into<DBType> for ConfigDatabase {
-    fn into(self) -> DBType {
-        match self.db_type.as_str() {
-            "External" => DBType::External(self.uri.to_string()),
-            _ => DBType::Embedded {
-                root_path: self.root_path.unwrap().into(),
-                port: self.port.unwrap() as i16,
-                username: self.username.unwrap(),
-                password: self.password.unwrap(),
-                persistent: self.persistent.unwrap(),
-                timeout: self.timeout.unwrap(),
-                host: self.host.unwrap(),
-            },
+impl ConfigDatabase {
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    pub fn log_config(&self) -> LogConfig {
+        LogConfig {
+            level: self
+                .log_level
+                .clone()
+                .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string()),
+            directory: self.log_dir.as_ref().map(PathBuf::from),
+            format: self.log_format.unwrap_or_default(),
+        }
+    }
+
+    /// Check what `ConfigBuilder::set` can't, the fields that depend on
+    /// each other
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.db_type == Backend::External && self.uri.is_empty() {
+            return Err(ConfigError::Missing {
+                field: "uri",
+                when: "with db_type External",
+            });
+        }
+        if self.db_type == Backend::External && ExternalServer::parse(&self.uri).is_err() {
+            return Err(ConfigError::Invalid {
+                field: "uri".to_string(),
+                expected: ExternalServer::EXPECTED,
+                value: format!("{:?}", redact_uri(&self.uri)),
+            });
+        }
+        if self.app_role().is_some() && self.client_certificates.unwrap_or(false) {
+            return Err(ConfigError::Conflict {
+                field: "app_role",
+                other: "client_certificates",
+            });
+        }
+        if cfg!(not(unix)) && self.in_memory.unwrap_or(false) {
+            return Err(ConfigError::Unsupported {
+                field: "in_memory",
+                platform: "unix",
+            });
+        }
+        if let Some(grants) = &self.app_role_grants {
+            if AppRole::parse_grants(grants).is_none() {
+                return Err(ConfigError::Invalid {
+                    field: "app_role_grants".to_string(),
+                    expected: GRANTS_EXPECTED,
+                    value: format!("{:?}", grants),
+                });
+            }
+        }
+        let invalid_port = |expected| ConfigError::Invalid {
+            field: "port".to_string(),
+            expected,
+            value: format!("{:?}", self.port.unwrap_or_default()),
+        };
+        match self.port {
+            Some(0) => Err(invalid_port("a port number (1-65535)")),
+            Some(port) if self.db_type == Backend::Embedded && port > MAX_EMBEDDED_PORT => {
+                Err(invalid_port("a port up to 32767 for an embedded cluster"))
+            }
+            _ => Ok(()),
         }
     }
+
+    /// The fields left out taken from the defaults, except the ones where
+    /// nothing means something, like `root_path` for a temporary workspace
+    pub(crate) fn with_defaults(self) -> Self {
+        let defaults = Self::default();
+        Self {
+            username: self.username.or(defaults.username),
+            password: self.password.or(defaults.password),
+            persistent: self.persistent.or(defaults.persistent),
+            timeout: self.timeout.or(defaults.timeout),
+            retry_interval: self.retry_interval.or(defaults.retry_interval),
+            max_retry_interval: self.max_retry_interval.or(defaults.max_retry_interval),
+            host: self.host.or(defaults.host),
+            reuse: self.reuse.or(defaults.reuse),
+            auth_method: self.auth_method.or(defaults.auth_method),
+            generate_password: self.generate_password.or(defaults.generate_password),
+            tls: self.tls.or(defaults.tls),
+            client_certificates: self.client_certificates.or(defaults.client_certificates),
+            replica: self.replica.or(defaults.replica),
+            logical_decoding: self.logical_decoding.or(defaults.logical_decoding),
+            in_memory: self.in_memory.or(defaults.in_memory),
+            memory_path: self.memory_path.or(defaults.memory_path),
+            keep_on_failure: self.keep_on_failure.or(defaults.keep_on_failure),
+            app_role: self.app_role.or(defaults.app_role),
+            log_level: self.log_level.or(defaults.log_level),
+            log_format: self.log_format.or(defaults.log_format),
+            ..self
+        }
+    }
+
+    /// The role to create with each database, if any
+    pub fn app_role(&self) -> Option<AppRole> {
+        match self
+            .app_role_grants
+            .as_deref()
+            .and_then(AppRole::parse_grants)
+        {
+            Some(grants) => Some(AppRole::Grants(grants)),
+            None if self.app_role.unwrap_or(false) => Some(AppRole::Owner),
+            None => None,
+        }
+    }
+
+    /// The tmpfs to keep the data on, when running in memory
+    pub fn memory_path(&self) -> Option<PathBuf> {
+        if !self.in_memory.unwrap_or(false) {
+            return None;
+        }
+        let path = self.memory_path.as_deref().unwrap_or(DEFAULT_MEMORY_PATH);
+        Some(PathBuf::from(path))
+    }
 }
 
 impl Default for ConfigDatabase {
     fn default() -> Self {
-        let root_path = match TempDir::new("db") {
-            Ok(v) => String::from(v.path().to_str().unwrap()),
-            Err(_) => ".".to_string(),
-        };
         Self {
-            db_type: "Embedded".to_string(),
-            uri: "127.0.0.1".to_string(),
+            db_type: Backend::Embedded,
+            // Only used by external servers, which have to set it
+            uri: String::new(),
             timeout: Some(Duration::from_secs(15)),
-            root_path: Some(root_path.to_string()),
+            retry_interval: Some(Duration::from_millis(100)),
+            max_retry_interval: Some(Duration::from_secs(2)),
+            // A temporary workspace is created for the cluster
+            root_path: None,
             username: Some("postgres".to_string()),
             password: Some("postgres".to_string()),
             persistent: Some(false),
             port: None, //Some(5433),
             // max_connections: 5,
             host: Some("https://repo1.maven.org".to_string()),
+            reuse: Some(false),
+            auth_method: Some(AuthMethod::default()),
+            generate_password: Some(false),
+            tls: Some(false),
+            client_certificates: Some(false),
+            replica: Some(false),
+            logical_decoding: Some(false),
+            in_memory: Some(false),
+            memory_path: Some(DEFAULT_MEMORY_PATH.to_string()),
+            keep_on_failure: Some(false),
+            app_role: Some(false),
+            app_role_grants: None,
+            log_level: Some(DEFAULT_LOG_LEVEL.to_string()),
+            log_dir: None,
+            log_format: Some(LogFormat::default()),
+        }
+    }
+}
+
+/// The `ConfigDatabase` fields `ConfigBuilder::set` knows, they're also the
+/// `pmem.toml` keys and, upper cased after `PMEM_`, the environment variables.
+/// `set` takes them in camelCase and with dashes too.
+pub const FIELDS: [&str; 26] = [
+    "db_type",
+    "uri",
+    "root_path",
+    "username",
+    "password",
+    "persistent",
+    "port",
+    "timeout",
+    "retry_interval",
+    "max_retry_interval",
+    "host",
+    "reuse",
+    "auth_method",
+    "generate_password",
+    "tls",
+    "client_certificates",
+    "replica",
+    "logical_decoding",
+    "in_memory",
+    "memory_path",
+    "keep_on_failure",
+    "app_role",
+    "app_role_grants",
+    "log_level",
+    "log_dir",
+    "log_format",
+];
+
+/// Builds a `ConfigDatabase` from the defaults, for use from Rust
+#[derive(Debug, Default)]
+pub struct ConfigBuilder {
+    config: ConfigDatabase,
+}
+
+macro_rules! setters {
+    ($($(#[$doc:meta])* $name:ident: $ty:ty),* $(,)?) => {
+        $(
+            $(#[$doc])*
+            pub fn $name(mut self, $name: impl Into<$ty>) -> Self {
+                self.config.$name = Some($name.into());
+                self
+            }
+        )*
+    };
+}
+
+impl ConfigBuilder {
+    /// Use a server that's already running instead of starting one
+    pub fn external(mut self, uri: impl Into<String>) -> Self {
+        self.config.db_type = Backend::External;
+        self.config.uri = uri.into();
+        self
+    }
+
+    pub fn root_path(mut self, root_path: impl AsRef<Path>) -> Self {
+        self.config.root_path = Some(root_path.as_ref().to_string_lossy().into_owned());
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = Some(port);
+        self
+    }
+
+    pub fn memory_path(mut self, memory_path: impl AsRef<Path>) -> Self {
+        self.config.memory_path = Some(memory_path.as_ref().to_string_lossy().into_owned());
+        self
+    }
+
+    pub fn log_dir(mut self, log_dir: impl AsRef<Path>) -> Self {
+        self.config.log_dir = Some(log_dir.as_ref().to_string_lossy().into_owned());
+        self
+    }
+
+    setters! {
+        username: String,
+        password: String,
+        persistent: bool,
+        /// How long to wait for the cluster to start, or for an external
+        /// server to accept connections
+        timeout: Duration,
+        retry_interval: Duration,
+        max_retry_interval: Duration,
+        /// Where to download postgres from
+        host: String,
+        reuse: bool,
+        auth_method: AuthMethod,
+        generate_password: bool,
+        tls: bool,
+        client_certificates: bool,
+        replica: bool,
+        logical_decoding: bool,
+        in_memory: bool,
+        keep_on_failure: bool,
+        app_role: bool,
+        /// Comma separated table privileges, checked by `validate`
+        app_role_grants: String,
+        log_level: String,
+        log_format: LogFormat,
+    }
+
+    /// Set a field from its text form, the way `pmem.toml`, the environment
+    /// and the command line spell it. `timeout` is in seconds, the retry
+    /// intervals in milliseconds.
+    pub fn set(mut self, field: &str, value: &str) -> Result<Self, ConfigError> {
+        let invalid = |expected| ConfigError::Invalid {
+            field: field.to_string(),
+            expected,
+            value: format!("{:?}", value),
+        };
+        let flag = || value.parse::<bool>().map_err(|_| invalid("true or false"));
+        let millis = || {
+            value
+                .parse()
+                .ok()
+                .filter(|ms| *ms != 0)
+                .map(Duration::from_millis)
+                .ok_or_else(|| invalid("a number of milliseconds above 0"))
+        };
+        Ok(match snake_case(field).as_str() {
+            "db_type" => {
+                self.config.db_type =
+                    parse_enum(value).ok_or_else(|| invalid("Embedded or External"))?;
+                self
+            }
+            "uri" => {
+                self.config.uri = value.to_string();
+                self
+            }
+            "root_path" => self.root_path(value),
+            "username" => self.username(value),
+            "password" => self.password(value),
+            "persistent" => self.persistent(flag()?),
+            "port" => self.port(
+                value
+                    .parse()
+                    .ok()
+                    .filter(|port| *port != 0)
+                    .ok_or_else(|| invalid("a port number (1-65535)"))?,
+            ),
+            "timeout" => self.timeout(Duration::from_secs(
+                value.parse().map_err(|_| invalid("a number of seconds"))?,
+            )),
+            "retry_interval" => self.retry_interval(millis()?),
+            "max_retry_interval" => self.max_retry_interval(millis()?),
+            "host" => self.host(value),
+            "reuse" => self.reuse(flag()?),
+            "auth_method" => self.auth_method(
+                parse_enum::<AuthMethod>(value)
+                    .ok_or_else(|| invalid("trust, password, md5 or scram-sha-256"))?,
+            ),
+            "generate_password" => self.generate_password(flag()?),
+            "tls" => self.tls(flag()?),
+            "client_certificates" => self.client_certificates(flag()?),
+            "replica" => self.replica(flag()?),
+            "logical_decoding" => self.logical_decoding(flag()?),
+            "in_memory" => self.in_memory(flag()?),
+            "memory_path" => self.memory_path(value),
+            "keep_on_failure" => self.keep_on_failure(flag()?),
+            "app_role" => self.app_role(flag()?),
+            "app_role_grants" => match AppRole::parse_grants(value) {
+                Some(_) => self.app_role_grants(value),
+                None => return Err(invalid(GRANTS_EXPECTED)),
+            },
+            "log_level" => self.log_level(value),
+            "log_dir" => self.log_dir(value),
+            "log_format" => self
+                .log_format(parse_enum::<LogFormat>(value).ok_or_else(|| invalid("text or json"))?),
+            _ => return Err(ConfigError::Unknown(field.to_string())),
+        })
+    }
+
+    /// Set the fields of a JSON object, the Node bindings' options. Nulls
+    /// are skipped.
+    pub fn merge_json(mut self, options: &serde_json::Value) -> Result<Self, ConfigError> {
+        let options = match options {
+            serde_json::Value::Null => return Ok(self),
+            serde_json::Value::Object(options) => options,
+            _ => {
+                return Err(ConfigError::Invalid {
+                    field: "options".to_string(),
+                    expected: "an object",
+                    value: options.to_string(),
+                })
+            }
+        };
+        for (field, value) in options {
+            let text = match value {
+                serde_json::Value::Null => continue,
+                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::Bool(_) | serde_json::Value::Number(_) => value.to_string(),
+                _ => {
+                    return Err(ConfigError::Invalid {
+                        field: field.clone(),
+                        expected: "a string, a number or a boolean",
+                        value: value.to_string(),
+                    })
+                }
+            };
+            self = self.set(field, &text)?;
         }
+        Ok(self)
     }
+
+    /// Apply `pmem.toml` and then the `PMEM_*` environment variables on top
+    /// of the fields set so far. The fields set afterwards override both.
+    pub fn load(self) -> anyhow::Result<Self> {
+        let builder = match config_file::find()? {
+            Some(path) => config_file::apply_file(self, &path)?,
+            None => self,
+        };
+        config_file::apply_env(builder, std::env::vars_os())
+    }
+
+    pub fn build(self) -> ConfigDatabase {
+        self.config
+    }
+}
+
+/// Enums are spelled the way the JSON configuration spells them
+fn parse_enum<T: DeserializeOwned>(value: &str) -> Option<T> {
+    serde_json::from_value(serde_json::Value::String(value.to_string())).ok()
+}
+
+/// `root_path` for `rootPath` and `root-path`
+fn snake_case(field: &str) -> String {
+    let mut snake = String::with_capacity(field.len() + 4);
+    for c in field.chars() {
+        match c {
+            '-' => snake.push('_'),
+            c if c.is_ascii_uppercase() => {
+                snake.push('_');
+                snake.push(c.to_ascii_lowercase());
+            }
+            c => snake.push(c),
+        }
+    }
+    snake
 }
 
 #[cfg(test)]
@@ -67,6 +551,148 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn test_config_builder_starts_from_the_defaults() {
+        let config = ConfigDatabase::builder()
+            .root_path(Path::new("/tmp/pmem"))
+            .port(5499)
+            .tls(true)
+            .auth_method(AuthMethod::ScramSha256)
+            .build();
+        assert_eq!(config.root_path.as_deref(), Some("/tmp/pmem"));
+        assert_eq!(config.port, Some(5499));
+        assert_eq!(config.tls, Some(true));
+        assert_eq!(config.auth_method, Some(AuthMethod::ScramSha256));
+        assert_eq!(config.username, ConfigDatabase::default().username);
+
+        let config = ConfigDatabase::builder().external("postgres://db").build();
+        assert_eq!(config.db_type, Backend::External);
+        assert_eq!(config.uri, "postgres://db");
+    }
+
+    #[test]
+    fn test_config_fields_can_all_be_set() {
+        let defaults = serde_json::to_value(ConfigDatabase::default()).unwrap();
+        let mut fields: Vec<&str> = defaults
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        fields.sort_unstable();
+        let mut known = FIELDS.to_vec();
+        known.sort_unstable();
+        assert_eq!(fields, known);
+
+        let config = ConfigDatabase::builder()
+            .merge_json(&serde_json::json!({
+                "db_type": "External",
+                "uri": "postgres://db",
+                "port": 5499,
+                "timeout": 30,
+                "retryInterval": 250,
+                "tls": true,
+                "auth_method": "scram-sha-256",
+                "root_path": null,
+            }))
+            .unwrap()
+            .build();
+        assert_eq!(config.db_type, Backend::External);
+        assert_eq!(config.uri, "postgres://db");
+        assert_eq!(config.port, Some(5499));
+        assert_eq!(config.timeout, Some(Duration::from_secs(30)));
+        assert_eq!(config.retry_interval, Some(Duration::from_millis(250)));
+        assert_eq!(config.tls, Some(true));
+        assert_eq!(config.auth_method, Some(AuthMethod::ScramSha256));
+        assert_eq!(config.root_path, None);
+
+        let config = ConfigDatabase::builder()
+            .merge_json(&serde_json::json!({"dbType": "external", "rootPath": "/tmp/db"}))
+            .unwrap()
+            .set("log-format", "json")
+            .unwrap()
+            .build();
+        assert_eq!(config.db_type, Backend::External);
+        assert_eq!(config.root_path.as_deref(), Some("/tmp/db"));
+        assert_eq!(config.log_format, Some(LogFormat::Json));
+    }
+
+    #[test]
+    fn test_config_errors_name_the_field() {
+        let error = |field, value| {
+            ConfigDatabase::builder()
+                .set(field, value)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("db_type", "Remote"),
+            "db_type: expected Embedded or External, got \"Remote\""
+        );
+        assert_eq!(
+            error("port", "70000"),
+            "port: expected a port number (1-65535), got \"70000\""
+        );
+        assert_eq!(
+            error("keepOnFailure", "yes"),
+            "keepOnFailure: expected true or false, got \"yes\""
+        );
+        assert_eq!(
+            error("retry-interval", "0"),
+            "retry-interval: expected a number of milliseconds above 0, got \"0\""
+        );
+        assert_eq!(
+            error("app_role_grants", "SELECT, DROP"),
+            "app_role_grants: expected a comma separated list of table privileges, got \"SELECT, DROP\""
+        );
+        assert_eq!(error("colour", "red"), "colour: unknown option");
+        let json_error = ConfigDatabase::builder()
+            .merge_json(&serde_json::json!({"tls": [true]}))
+            .unwrap_err();
+        assert_eq!(
+            json_error.to_string(),
+            "tls: expected a string, a number or a boolean, got [true]"
+        );
+
+        let config = ConfigDatabase::builder().build();
+        assert_eq!(config.validate(), Ok(()));
+        let config = ConfigDatabase {
+            db_type: Backend::External,
+            ..ConfigDatabase::default()
+        };
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "uri: required with db_type External"
+        );
+        let config = ConfigDatabase::builder()
+            .external("mysql://root:secret@db")
+            .build();
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "uri: expected a postgres:// URI, got \"mysql://root:********@db\""
+        );
+        let config = ConfigDatabase::builder()
+            .app_role(true)
+            .client_certificates(true)
+            .build();
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "app_role: can't be combined with client_certificates"
+        );
+        let config = ConfigDatabase::builder().port(40000).build();
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "port: expected a port up to 32767 for an embedded cluster, got 40000"
+        );
+        assert!(ConfigDatabase::builder()
+            .external("postgres://db:40000")
+            .port(40000)
+            .build()
+            .validate()
+            .is_ok());
+        assert!(serde_json::from_str::<ConfigDatabase>("{\"colour\": \"red\"}").is_err());
+    }
+
     #[test]
     fn test_config_deserializes_duration() {
         serde_json_eq!(
@@ -0,0 +1,157 @@
+//! Configuration from `pmem.toml` and the `PMEM_*` environment variables.
+//! The file is TOML whose top level holds the fields as strings, integers
+//! and booleans.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context};
+
+use super::config::{ConfigBuilder, FIELDS};
+
+pub const FILE_NAME: &str = "pmem.toml";
+/// Points at the file to use instead of looking for one
+pub const FILE_VAR: &str = "PMEM_CONFIG";
+const ENV_PREFIX: &str = "PMEM_";
+/// Relative paths in a file are relative to the file's directory
+const PATH_FIELDS: [&str; 3] = ["root_path", "memory_path", "log_dir"];
+
+/// `PMEM_CONFIG`, or the closest `pmem.toml` in the working directory or
+/// above it
+pub fn find() -> anyhow::Result<Option<PathBuf>> {
+    if let Some(path) = env::var_os(FILE_VAR) {
+        return Ok(Some(PathBuf::from(path)));
+    }
+    let dir = env::current_dir()?;
+    Ok(dir
+        .ancestors()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file()))
+}
+
+pub fn apply_file(mut builder: ConfigBuilder, path: &Path) -> anyhow::Result<ConfigBuilder> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Unable to read the configuration in {:?}", path))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    log::debug!("Reading the configuration in {:?}", path);
+    let fields: BTreeMap<String, toml::Value> =
+        toml::from_str(&text).with_context(|| path.display().to_string())?;
+    for (field, value) in fields {
+        let mut value = match value {
+            toml::Value::String(s) => s,
+            toml::Value::Integer(_) | toml::Value::Boolean(_) => value.to_string(),
+            other => bail!(
+                "{}: {}: expected a string, an integer or a boolean, got {}",
+                path.display(),
+                field,
+                other.type_str()
+            ),
+        };
+        if PATH_FIELDS.contains(&field.as_str()) {
+            value = dir.join(&value).to_string_lossy().into_owned();
+        }
+        builder = builder
+            .set(&field, &value)
+            .with_context(|| path.display().to_string())?;
+    }
+    Ok(builder)
+}
+
+/// The `PMEM_<FIELD>` variables, empty ones and the ones that aren't a
+/// field (like the daemon's `PMEM_TOKEN`) are skipped
+pub fn apply_env(
+    mut builder: ConfigBuilder,
+    vars: impl IntoIterator<Item = (OsString, OsString)>,
+) -> anyhow::Result<ConfigBuilder> {
+    for (key, value) in vars {
+        let field = match key.to_str().and_then(|k| k.strip_prefix(ENV_PREFIX)) {
+            Some(field) => field.to_lowercase(),
+            None => continue,
+        };
+        if value.is_empty() || !FIELDS.contains(&field.as_str()) {
+            continue;
+        }
+        let key = key.to_string_lossy();
+        let value = value
+            .to_str()
+            .ok_or_else(|| anyhow!("{} isn't UTF-8", key))?;
+        builder = builder
+            .set(&field, value)
+            .with_context(|| key.to_string())?;
+    }
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::system::{AuthMethod, Backend, ConfigDatabase};
+
+    #[test]
+    fn test_config_layers_file_then_env() {
+        let dir = tempdir::TempDir::new("pmem-config").unwrap();
+        let path = dir.path().join(FILE_NAME);
+        fs::write(
+            &path,
+            "# CI points this at a server\n\
+             db_type = \"External\"\n\
+             uri = \"postgres://file\"\n\
+             root_path = \".pmem\"\n\
+             port = 5499\n\
+             timeout = 30\n",
+        )
+        .unwrap();
+
+        let builder = ConfigDatabase::builder().tls(true);
+        let builder = apply_file(builder, &path).unwrap();
+        let vars = vec![
+            ("PMEM_URI", "postgres://env"),
+            ("PMEM_AUTH_METHOD", "scram-sha-256"),
+            ("PMEM_PORT", ""),
+            ("PMEM_TOKEN", "secret"),
+            ("PORT", "1"),
+        ];
+        let vars = vars
+            .into_iter()
+            .map(|(k, v)| (OsString::from(k), OsString::from(v)));
+        let config = apply_env(builder, vars).unwrap().port(5500).build();
+
+        assert_eq!(config.db_type, Backend::External);
+        assert_eq!(config.uri, "postgres://env");
+        assert_eq!(
+            config.root_path.map(PathBuf::from),
+            Some(dir.path().join(".pmem"))
+        );
+        assert_eq!(config.port, Some(5500));
+        assert_eq!(config.timeout, Some(Duration::from_secs(30)));
+        assert_eq!(config.tls, Some(true));
+        assert_eq!(config.auth_method, Some(AuthMethod::ScramSha256));
+
+        fs::write(&path, "port = 5499\ncolour = \"red\"\n").unwrap();
+        let error = apply_file(ConfigDatabase::builder(), &path).unwrap_err();
+        assert!(
+            format!("{:#}", error).ends_with("pmem.toml: colour: unknown option"),
+            "{:#}",
+            error
+        );
+        fs::write(&path, "[pmem]\nport = 5499\n").unwrap();
+        let error = apply_file(ConfigDatabase::builder(), &path).unwrap_err();
+        assert!(
+            format!("{:#}", error)
+                .ends_with("pmem: expected a string, an integer or a boolean, got table"),
+            "{:#}",
+            error
+        );
+        fs::write(&path, "port = \"5499").unwrap();
+        assert!(apply_file(ConfigDatabase::builder(), &path).is_err());
+        let vars = vec![(OsString::from("PMEM_PORT"), OsString::from("x"))];
+        assert!(apply_env(ConfigDatabase::builder(), vars).is_err());
+    }
+}
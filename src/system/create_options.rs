@@ -0,0 +1,270 @@
+//! How a database is created, beyond its name
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use super::{
+    config::ConfigError,
+    identifier::{self, quote_ident, quote_literal},
+};
+
+/// The first major version with `LOCALE_PROVIDER icu`
+const ICU_LOCALE_SINCE: u32 = 15;
+
+#[derive(Debug, Default, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CreateOptions {
+    /// The role owning the database, the admin user by default
+    pub owner: Option<String>,
+    /// The database to copy, it can't have any other sessions while that
+    /// happens. `template0` when an encoding or a locale is set.
+    pub template: Option<String>,
+    pub encoding: Option<String>,
+    #[serde(alias = "lcCollate")]
+    pub lc_collate: Option<String>,
+    #[serde(alias = "lcCtype")]
+    pub lc_ctype: Option<String>,
+    /// Use ICU for collations, with this locale (postgres 15 and later)
+    #[serde(alias = "icuLocale")]
+    pub icu_locale: Option<String>,
+    /// -1 for no limit
+    #[serde(alias = "connectionLimit")]
+    pub connection_limit: Option<i32>,
+    /// Settings sessions on the database start with, `ALTER DATABASE ... SET`
+    pub settings: BTreeMap<String, serde_json::Value>,
+}
+
+impl CreateOptions {
+    pub fn template(template: impl Into<String>) -> Self {
+        Self {
+            template: Some(template.into()),
+            ..Self::default()
+        }
+    }
+
+    /// Check what postgres would only reject halfway through creating the
+    /// database, and what `server_version` doesn't support when it's known
+    pub fn validate(&self, server_version: Option<&str>) -> Result<(), ConfigError> {
+        let invalid = |field: &str, expected, value: String| ConfigError::Invalid {
+            field: field.to_string(),
+            expected,
+            value,
+        };
+        for (field, name) in [("owner", &self.owner), ("template", &self.template)] {
+            if let Some(name) = name {
+                if let Err(expected) = identifier::check_name(name) {
+                    return Err(invalid(field, expected, format!("{:?}", name)));
+                }
+            }
+        }
+        if let Some(encoding) = &self.encoding {
+            if !is_word(encoding, "_-") {
+                return Err(invalid(
+                    "encoding",
+                    "an encoding name",
+                    format!("{:?}", encoding),
+                ));
+            }
+        }
+        for (field, locale) in [
+            ("lc_collate", &self.lc_collate),
+            ("lc_ctype", &self.lc_ctype),
+            ("icu_locale", &self.icu_locale),
+        ] {
+            if let Some(locale) = locale {
+                if !is_word(locale, "_-.@=") {
+                    return Err(invalid(field, "a locale name", format!("{:?}", locale)));
+                }
+            }
+        }
+        if let (Some(_), Some(version)) = (&self.icu_locale, server_version) {
+            if matches!(major_version(version), Some(major) if major < ICU_LOCALE_SINCE) {
+                return Err(invalid(
+                    "icu_locale",
+                    "a server running postgres 15 or later",
+                    version.to_string(),
+                ));
+            }
+        }
+        if let Some(limit) = self.connection_limit {
+            if limit < -1 {
+                return Err(invalid("connection_limit", "-1 or more", limit.to_string()));
+            }
+        }
+        for (name, value) in &self.settings {
+            let starts_with_letter =
+                name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_');
+            if !starts_with_letter || !is_word(name, "_.") {
+                return Err(invalid("settings", "setting names", format!("{:?}", name)));
+            }
+            if setting_value(value).is_none() {
+                let field = format!("settings.{}", name);
+                let expected = "a string, a number or a boolean";
+                return Err(invalid(&field, expected, value.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// The `CREATE DATABASE` statement, for options that passed `validate`
+    pub fn create_sql(&self, name: &str) -> String {
+        let mut sql = format!("CREATE DATABASE {}", quote_ident(name));
+        if let Some(owner) = &self.owner {
+            sql.push_str(&format!(" OWNER {}", quote_ident(owner)));
+        }
+        let sets_locale = self.encoding.is_some()
+            || self.lc_collate.is_some()
+            || self.lc_ctype.is_some()
+            || self.icu_locale.is_some();
+        match &self.template {
+            Some(template) => sql.push_str(&format!(" TEMPLATE {}", quote_ident(template))),
+            None if sets_locale => sql.push_str(" TEMPLATE template0"),
+            None => {}
+        }
+        for (option, value) in [
+            ("ENCODING", &self.encoding),
+            ("LC_COLLATE", &self.lc_collate),
+            ("LC_CTYPE", &self.lc_ctype),
+        ] {
+            if let Some(value) = value {
+                sql.push_str(&format!(" {} {}", option, quote_literal(value)));
+            }
+        }
+        if let Some(locale) = &self.icu_locale {
+            sql.push_str(&format!(
+                " LOCALE_PROVIDER icu ICU_LOCALE {}",
+                quote_literal(locale)
+            ));
+        }
+        if let Some(limit) = self.connection_limit {
+            sql.push_str(&format!(" CONNECTION LIMIT {}", limit));
+        }
+        sql
+    }
+
+    /// The `ALTER DATABASE ... SET` statements for `settings`, empty without
+    /// any
+    pub fn settings_sql(&self, name: &str) -> String {
+        self.settings
+            .iter()
+            .filter_map(|(setting, value)| {
+                Some(format!(
+                    "ALTER DATABASE {} SET {} = {};\n",
+                    quote_ident(name),
+                    quote_ident(setting),
+                    quote_literal(&setting_value(value)?)
+                ))
+            })
+            .collect()
+    }
+}
+
+/// `server_version`'s major version, `15` for `15.1 (Debian 15.1-1)`
+fn major_version(server_version: &str) -> Option<u32> {
+    let end = server_version
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(server_version.len());
+    server_version[..end].parse().ok()
+}
+
+fn setting_value(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Bool(_) | serde_json::Value::Number(_) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// ASCII letters and digits, and the characters in `extra`
+fn is_word(s: &str, extra: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || extra.contains(c))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_create_options_build_the_statements() {
+        let options: CreateOptions = serde_json::from_value(serde_json::json!({
+            "owner": "app",
+            "encoding": "UTF8",
+            "lcCollate": "C",
+            "icu_locale": "en-US",
+            "connectionLimit": 5,
+            "settings": {"statement_timeout": 1000, "TimeZone": "UTC"},
+        }))
+        .unwrap();
+        assert_eq!(options.validate(None), Ok(()));
+        assert_eq!(options.validate(Some("15.1 (Debian 15.1-1)")), Ok(()));
+        assert_eq!(
+            options.create_sql("my \"db\""),
+            "CREATE DATABASE \"my \"\"db\"\"\" OWNER \"app\" TEMPLATE template0 \
+             ENCODING 'UTF8' LC_COLLATE 'C' LOCALE_PROVIDER icu ICU_LOCALE 'en-US' \
+             CONNECTION LIMIT 5"
+        );
+        assert_eq!(
+            options.settings_sql("db"),
+            "ALTER DATABASE \"db\" SET \"TimeZone\" = 'UTC';\n\
+             ALTER DATABASE \"db\" SET \"statement_timeout\" = '1000';\n"
+        );
+        assert_eq!(
+            CreateOptions::template("fixtures").create_sql("db"),
+            "CREATE DATABASE \"db\" TEMPLATE \"fixtures\""
+        );
+        assert!(serde_json::from_str::<CreateOptions>("{\"colour\": \"red\"}").is_err());
+    }
+
+    #[test]
+    fn test_create_options_are_validated() {
+        let error = |options: serde_json::Value| {
+            serde_json::from_value::<CreateOptions>(options)
+                .unwrap()
+                .validate(None)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error(serde_json::json!({"encoding": "UTF8'; DROP"})),
+            "encoding: expected an encoding name, got \"UTF8'; DROP\""
+        );
+        assert_eq!(
+            error(serde_json::json!({"lc_ctype": "en US"})),
+            "lc_ctype: expected a locale name, got \"en US\""
+        );
+        assert_eq!(
+            error(serde_json::json!({"connection_limit": -2})),
+            "connection_limit: expected -1 or more, got -2"
+        );
+        assert_eq!(
+            error(serde_json::json!({"owner": ""})),
+            format!("owner: expected {}, got \"\"", identifier::EXPECTED)
+        );
+        assert_eq!(
+            error(serde_json::json!({"template": "a\"; DROP"})),
+            format!(
+                "template: expected {}, got \"a\\\"; DROP\"",
+                identifier::EXPECTED
+            )
+        );
+        assert_eq!(
+            error(serde_json::json!({"settings": {"work mem": "4MB"}})),
+            "settings: expected setting names, got \"work mem\""
+        );
+        assert_eq!(
+            error(serde_json::json!({"settings": {"work_mem": ["4MB"]}})),
+            "settings.work_mem: expected a string, a number or a boolean, got [\"4MB\"]"
+        );
+        let options = CreateOptions {
+            icu_locale: Some("en-US".to_string()),
+            ..CreateOptions::default()
+        };
+        assert_eq!(
+            options.validate(Some("14.3.0")).unwrap_err().to_string(),
+            "icu_locale: expected a server running postgres 15 or later, got 14.3.0"
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! A C ABI over `Cluster` for languages that load shared libraries but can't
+//! use the Node bindings, Python's ctypes or Go's cgo. `include/pmem.h`
+//! declares it, regenerate it with `cbindgen --output include/pmem.h` after
+//! changing a signature here.
+//!
+//! Functions returning an `int` return 0 on success and -1 on failure, the
+//! ones returning a pointer return NULL. `pmem_last_error` then says what
+//! went wrong. Strings pmem hands out are freed with `pmem_string_free`.
+
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+use anyhow::{anyhow, bail, Context};
+use tokio::runtime::Runtime;
+
+use super::{
+    cluster::Cluster,
+    config::{ConfigBuilder, ConfigDatabase},
+    logger::init_logging,
+};
+
+/// A cluster and the runtime its calls block on. It's started once, with
+/// the configuration it was created with.
+pub struct PmemHandle {
+    runtime: Runtime,
+    config: Option<ConfigDatabase>,
+    cluster: Option<Cluster>,
+}
+
+impl PmemHandle {
+    fn cluster(&mut self) -> anyhow::Result<&mut Cluster> {
+        self.cluster
+            .as_mut()
+            .ok_or_else(|| anyhow!("The cluster isn't running, call pmem_start first"))
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Run `f`, turning its errors and panics into `failed` and the thread's
+/// last error
+fn call<T>(failed: T, f: impl FnOnce() -> anyhow::Result<T>) -> T {
+    let error = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => {
+            LAST_ERROR.with(|e| e.borrow_mut().take());
+            return value;
+        }
+        Ok(Err(e)) => format!("{:#}", e),
+        Err(panic) => match panic.downcast_ref::<&str>() {
+            Some(message) => format!("pmem panicked: {}", message),
+            None => match panic.downcast_ref::<String>() {
+                Some(message) => format!("pmem panicked: {}", message),
+                None => "pmem panicked".to_string(),
+            },
+        },
+    };
+    log::debug!("{}", error);
+    let error = CString::new(error.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(error));
+    failed
+}
+
+unsafe fn handle<'a>(handle: *mut PmemHandle) -> anyhow::Result<&'a mut PmemHandle> {
+    handle.as_mut().context("The handle is NULL")
+}
+
+unsafe fn string<'a>(s: *const c_char, what: &str) -> anyhow::Result<&'a str> {
+    if s.is_null() {
+        bail!("The {} is NULL", what);
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .with_context(|| format!("The {} isn't UTF-8", what))
+}
+
+fn into_c_string(s: String) -> anyhow::Result<*mut c_char> {
+    Ok(CString::new(s)?.into_raw())
+}
+
+/// Create a handle from a JSON configuration, the Node bindings' fields,
+/// applied over `pmem.toml` and the `PMEM_*` variables. NULL applies none.
+///
+/// # Safety
+///
+/// `config_json` is NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn pmem_init(config_json: *const c_char) -> *mut PmemHandle {
+    call(ptr::null_mut(), || {
+        let options: serde_json::Value = match config_json.is_null() {
+            true => serde_json::Value::Null,
+            false => serde_json::from_str(string(config_json, "configuration")?)
+                .context("Invalid configuration")?,
+        };
+        let config = ConfigBuilder::default()
+            .load()?
+            .merge_json(&options)
+            .context("Invalid configuration")?
+            .build();
+        let _ = init_logging(&config.log_config());
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        Ok(Box::into_raw(Box::new(PmemHandle {
+            runtime,
+            config: Some(config),
+            cluster: None,
+        })))
+    })
+}
+
+/// Start the embedded cluster, or connect to the external server
+///
+/// # Safety
+///
+/// `handle` comes from `pmem_init` and wasn't freed.
+#[no_mangle]
+pub unsafe extern "C" fn pmem_start(handle: *mut PmemHandle) -> c_int {
+    call(-1, || {
+        let handle = self::handle(handle)?;
+        let config = handle
+            .config
+            .take()
+            .context("The cluster was already started")?;
+        handle.cluster = Some(handle.runtime.block_on(Cluster::start(config))?);
+        Ok(0)
+    })
+}
+
+/// Create a database, named by pmem when `name` is NULL, and return its URI
+///
+/// # Safety
+///
+/// `handle` comes from `pmem_init` and wasn't freed, `name` is NULL or a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn pmem_create_db(
+    handle: *mut PmemHandle,
+    name: *const c_char,
+) -> *mut c_char {
+    call(ptr::null_mut(), || {
+        let handle = self::handle(handle)?;
+        let name = match name.is_null() {
+            true => None,
+            false => Some(string(name, "database name")?),
+        };
+        let runtime = handle.runtime.handle().clone();
+        into_c_string(runtime.block_on(handle.cluster()?.create_database(name))?)
+    })
+}
+
+/// Drop a database
+///
+/// # Safety
+///
+/// `handle` comes from `pmem_init` and wasn't freed, `name` is a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn pmem_drop_db(handle: *mut PmemHandle, name: *const c_char) -> c_int {
+    call(-1, || {
+        let handle = self::handle(handle)?;
+        let name = string(name, "database name")?;
+        let runtime = handle.runtime.handle().clone();
+        runtime.block_on(handle.cluster()?.drop_database(name))?;
+        Ok(0)
+    })
+}
+
+/// Run the sqlx migrations in `dir` on a database
+///
+/// # Safety
+///
+/// `handle` comes from `pmem_init` and wasn't freed, `name` and `dir` are
+/// NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn pmem_migrate(
+    handle: *mut PmemHandle,
+    name: *const c_char,
+    dir: *const c_char,
+) -> c_int {
+    call(-1, || {
+        let handle = self::handle(handle)?;
+        let name = string(name, "database name")?;
+        let dir = string(dir, "migrations directory")?;
+        let runtime = handle.runtime.handle().clone();
+        runtime.block_on(handle.cluster()?.migrate(name, dir))?;
+        Ok(0)
+    })
+}
+
+/// Stop the cluster. The handle still has to be freed.
+///
+/// # Safety
+///
+/// `handle` comes from `pmem_init` and wasn't freed.
+#[no_mangle]
+pub unsafe extern "C" fn pmem_stop(handle: *mut PmemHandle) -> c_int {
+    call(-1, || {
+        let handle = self::handle(handle)?;
+        let cluster = handle.cluster.take().context("The cluster isn't running")?;
+        handle.runtime.block_on(cluster.stop())?;
+        Ok(0)
+    })
+}
+
+/// Free a handle, stopping its cluster if it's still running
+///
+/// # Safety
+///
+/// `handle` is NULL or comes from `pmem_init` and wasn't freed.
+#[no_mangle]
+pub unsafe extern "C" fn pmem_free(handle: *mut PmemHandle) {
+    if handle.is_null() {
+        return;
+    }
+    call((), || {
+        let handle = Box::from_raw(handle);
+        if let Some(cluster) = handle.cluster {
+            handle.runtime.block_on(cluster.stop())?;
+        }
+        Ok(())
+    })
+}
+
+/// What made the last call on this thread fail, NULL if it succeeded. The
+/// string belongs to pmem and is valid until the thread's next call.
+#[no_mangle]
+pub extern "C" fn pmem_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Free a string pmem returned
+///
+/// # Safety
+///
+/// `s` is NULL or comes from pmem and wasn't freed.
+#[no_mangle]
+pub unsafe extern "C" fn pmem_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ffi_reports_errors_through_last_error() {
+        unsafe {
+            let config = CString::new("{\"port\": \"not a port\"}").unwrap();
+            assert!(pmem_init(config.as_ptr()).is_null());
+            let error = CStr::from_ptr(pmem_last_error()).to_str().unwrap();
+            assert!(error.starts_with("Invalid configuration"), "{}", error);
+
+            let handle = pmem_init(ptr::null());
+            assert!(!handle.is_null());
+            assert!(pmem_last_error().is_null());
+            let name = CString::new("app").unwrap();
+            assert_eq!(pmem_drop_db(handle, name.as_ptr()), -1);
+            let error = CStr::from_ptr(pmem_last_error()).to_str().unwrap();
+            assert!(error.contains("pmem_start"), "{}", error);
+            assert_eq!(pmem_drop_db(ptr::null_mut(), name.as_ptr()), -1);
+            pmem_free(handle);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Database and role names on their way into SQL and URIs. Names pmem is
+//! handed or generates go through `check_name` or `check_db_name` before
+//! they reach either.
+
+use std::str::FromStr;
+
+use anyhow::bail;
+use percent_encoding::percent_decode_str;
+use sqlx::postgres::PgConnectOptions;
+use url::Url;
+
+use super::redact::redact;
+
+/// Longer identifiers are truncated by postgres
+pub const MAX_LENGTH: usize = 63;
+/// The databases every cluster comes with, they aren't pmem's to create or
+/// drop
+pub const RESERVED_DATABASES: [&str; 3] = ["postgres", "template0", "template1"];
+/// What `check_name` takes, for error messages
+pub const EXPECTED: &str = "a name of 1 to 63 bytes of letters, digits, spaces and `_-.$`";
+
+/// `Err` with what a name should look like, unless postgres takes `name` as
+/// is and it stays readable in logs and shells
+pub fn check_name(name: &str) -> Result<(), &'static str> {
+    let allowed = |c: char| c.is_alphanumeric() || "_-.$ ".contains(c);
+    if name.is_empty()
+        || name.len() > MAX_LENGTH
+        || name.trim() != name
+        || !name.chars().all(allowed)
+    {
+        return Err(EXPECTED);
+    }
+    Ok(())
+}
+
+/// Check `name` can be a database pmem creates or drops
+pub fn check_db_name(name: &str) -> anyhow::Result<()> {
+    if let Err(expected) = check_name(name) {
+        bail!("Invalid database name {:?}, expected {}", name, expected);
+    }
+    if RESERVED_DATABASES.contains(&name) {
+        bail!("The database name {:?} is reserved", name);
+    }
+    Ok(())
+}
+
+/// `ident` as a quoted identifier
+pub fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// `literal` as a quoted string literal
+pub fn quote_literal(literal: &str) -> String {
+    format!("'{}'", literal.replace('\'', "''"))
+}
+
+/// Point `url` at the database `name`, percent-encoded
+pub fn set_database(url: &mut Url, name: &str) {
+    if let Ok(mut path) = url.path_segments_mut() {
+        path.clear().push(name);
+    }
+}
+
+/// The options to connect to `uri` with. sqlx takes the database from the
+/// URI's path without percent-decoding it.
+pub fn connect_options(uri: &str) -> anyhow::Result<PgConnectOptions> {
+    let options =
+        PgConnectOptions::from_str(uri).map_err(|e| anyhow::anyhow!(redact(&e.to_string())))?;
+    let url = Url::parse(uri)?;
+    let database = percent_decode_str(url.path().trim_start_matches('/')).decode_utf8()?;
+    Ok(match database.is_empty() {
+        true => options,
+        false => options.database(&database),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_identifier_names_are_checked() {
+        for name in [
+            "app",
+            "my app",
+            "app-2.test",
+            "ünïcode",
+            "a$b",
+            &"a".repeat(63),
+        ] {
+            assert_eq!(check_name(name), Ok(()), "{}", name);
+        }
+        for name in [
+            "",
+            " app",
+            "app ",
+            "a\"b",
+            "a'b",
+            "a/b",
+            "a%b",
+            "a;b",
+            "a\nb",
+            &"a".repeat(64),
+        ] {
+            assert_eq!(check_name(name), Err(EXPECTED), "{:?}", name);
+        }
+
+        assert!(check_db_name("app").is_ok());
+        for name in RESERVED_DATABASES {
+            let error = check_db_name(name).unwrap_err().to_string();
+            assert!(error.contains("reserved"), "{}", error);
+        }
+        assert_eq!(
+            check_db_name("a'b").unwrap_err().to_string(),
+            format!("Invalid database name \"a'b\", expected {}", EXPECTED)
+        );
+    }
+
+    #[test]
+    fn test_identifier_quoting_and_uris() {
+        assert_eq!(quote_ident("my \"db\""), "\"my \"\"db\"\"\"");
+        assert_eq!(quote_literal("it's"), "'it''s'");
+
+        let mut url = Url::parse("postgres://u:p@db:5432/admin?sslmode=require").unwrap();
+        set_database(&mut url, "my app/%");
+        assert_eq!(
+            url.as_str(),
+            "postgres://u:p@db:5432/my%20app%2F%25?sslmode=require"
+        );
+        let options = connect_options(url.as_str()).unwrap();
+        assert_eq!(options.get_database(), Some("my app/%"));
+        let options = connect_options("postgres://u:p@db:5432").unwrap();
+        assert_eq!(options.get_database(), None);
+    }
+}
@@ -0,0 +1,133 @@
+#[cfg(unix)]
+use std::{
+    ffi::CString,
+    fs::DirBuilder,
+    io,
+    os::unix::{ffi::OsStrExt, fs::DirBuilderExt},
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::bail;
+
+use super::shutdown;
+
+/// Where in-memory clusters live unless `memory_path` says otherwise
+pub const DEFAULT_MEMORY_PATH: &str = "/dev/shm";
+/// Enough for a fresh cluster, its first WAL segments and some test data
+pub const MIN_FREE_SPACE: u64 = 256 * 1024 * 1024;
+
+/// A directory on a tmpfs holding everything the cluster writes. Nothing in
+/// it is meant to outlive the cluster, so it's removed when dropped and, in
+/// case we never get that far, when the process exits.
+#[derive(Debug)]
+pub struct MemoryDir {
+    pub path: PathBuf,
+}
+
+impl MemoryDir {
+    /// Make a private directory under `base`, provided it has `min_free`
+    /// bytes to spare
+    #[cfg(unix)]
+    pub fn create(base: &Path, min_free: u64) -> anyhow::Result<Self> {
+        if !base.is_dir() {
+            bail!(
+                "{:?} doesn't exist, point `memory_path` at a tmpfs mount to run in memory",
+                base
+            );
+        }
+        let free = free_space(base)?;
+        if free < min_free {
+            bail!(
+                "Only {} MiB free on {:?}, running in memory needs at least {} MiB. Free up space or point `memory_path` at a bigger tmpfs",
+                free / 1024 / 1024,
+                base,
+                min_free / 1024 / 1024
+            );
+        }
+
+        let path = base.join(format!("pmem-{}", cuid::cuid()?));
+        DirBuilder::new().mode(0o700).create(&path)?;
+        log::info!("Keeping the cluster in memory in {:?}", path);
+        shutdown::remove_on_exit(path.clone());
+        Ok(Self { path })
+    }
+
+    /// There's no tmpfs to check for, config validation rejects `in_memory`
+    #[cfg(not(unix))]
+    pub fn create(_base: &Path, _min_free: u64) -> anyhow::Result<Self> {
+        bail!("in_memory is only supported on unix")
+    }
+
+    /// Leave the directory behind for a post-mortem
+    pub fn keep(self) {
+        shutdown::forget_removal(&self.path);
+        log::warn!(
+            "Keeping the in-memory data in {:?} for debugging",
+            self.path
+        );
+        std::mem::forget(self);
+    }
+
+    /// Only call this once the cluster is down
+    pub fn remove(&self) {
+        shutdown::forget_removal(&self.path);
+        if !self.path.exists() {
+            return;
+        }
+        log::debug!("Removing the in-memory data in {:?}", self.path);
+        if let Err(e) = fs::remove_dir_all(&self.path) {
+            log::warn!("Unable to remove {:?}: {:?}", self.path, e.to_string());
+        }
+    }
+}
+
+impl Drop for MemoryDir {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+pub fn free_space(path: &Path) -> anyhow::Result<u64> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        bail!(
+            "Unable to check the free space on {:?}: {}",
+            path,
+            io::Error::last_os_error()
+        );
+    }
+    // The field types differ between platforms
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::os::unix::fs::PermissionsExt;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_memory_dir_is_private_and_checks_for_space() {
+        let base = TempDir::new("memory").unwrap();
+        assert!(free_space(base.path()).unwrap() > 0);
+
+        let err = MemoryDir::create(base.path(), u64::MAX).unwrap_err();
+        assert!(err.to_string().contains("memory_path"), "{}", err);
+        assert!(MemoryDir::create(&base.path().join("missing"), 0).is_err());
+
+        let dir = MemoryDir::create(base.path(), 0).unwrap();
+        let mode = fs::metadata(&dir.path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        dir.remove();
+        assert!(!dir.path.exists());
+    }
+}
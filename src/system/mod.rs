@@ -1,26 +1,76 @@
+mod app_role;
+mod auth;
+mod change_capture;
+mod cluster;
 mod config;
+mod config_file;
+mod create_options;
 mod db;
+mod external;
+#[cfg(feature = "ffi")]
+mod ffi;
+mod identifier;
 mod logger;
+mod memory;
+mod pg_conf;
+mod redact;
+mod replica;
+mod server_log;
+mod shutdown;
+mod state;
 mod system;
+#[cfg(feature = "node")]
 mod system_server;
+pub mod testing;
+mod tls;
 mod utils;
+mod workspace;
 
+pub use self::{
+    auth::AuthMethod,
+    change_capture::{ChangeKind, RowChange},
+    cluster::{Cluster, Database},
+    config::{Backend, ConfigBuilder, ConfigDatabase, ConfigError},
+    create_options::CreateOptions,
+    logger::{add_listener, init_logging, LogConfig, LogFormat, LogRecord},
+    replica::DatabaseUris,
+    server_log::{ServerLogEntry, ServerLogFilter},
+    shutdown::{stop_all, StopMode, StopOptions},
+    state::ClusterState,
+    tls::TlsFiles,
+};
+
+#[cfg(feature = "node")]
 use neon::prelude::*;
 
+#[cfg(feature = "node")]
 use self::system_server::SystemServer;
 
+#[cfg(feature = "node")]
 fn hello(mut cx: FunctionContext) -> JsResult<JsString> {
     Ok(cx.string("hello node"))
 }
 
+#[cfg(feature = "node")]
 pub fn neon_main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("hello", hello)?;
     cx.export_function("init_db", SystemServer::js_init)?;
     cx.export_function("start_db", SystemServer::js_start)?;
     cx.export_function("stop_db", SystemServer::js_stop)?;
     cx.export_function("new_db", SystemServer::js_create_new_db)?;
+    cx.export_function("new_db_uris", SystemServer::js_create_new_db_uris)?;
+    cx.export_function("pause_replay", SystemServer::js_pause_replay)?;
+    cx.export_function("resume_replay", SystemServer::js_resume_replay)?;
+    cx.export_function("capture_changes", SystemServer::js_start_change_capture)?;
+    cx.export_function("read_changes", SystemServer::js_read_changes)?;
+    cx.export_function("end_capture", SystemServer::js_end_change_capture)?;
     cx.export_function("execute_sql", SystemServer::js_execute_sql)?;
     cx.export_function("db_migration", SystemServer::js_execute_migrations)?;
     cx.export_function("drop_db", SystemServer::js_drop_database)?;
+    cx.export_function("shutdown_all", SystemServer::js_shutdown_all)?;
+    cx.export_function("server_logs", SystemServer::js_server_logs)?;
+    cx.export_function("on_server_log", SystemServer::js_on_server_log)?;
+    cx.export_function("tls_files", SystemServer::js_tls_files)?;
+    cx.export_function("on_log", SystemServer::js_on_log)?;
     Ok(())
 }
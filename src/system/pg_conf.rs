@@ -0,0 +1,164 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use super::auth::AuthMethod;
+
+pub const PMEM_CONF_FILE_NAME: &str = "pmem.conf";
+const PG_HBA_FILE_NAME: &str = "pg_hba.conf";
+const INCLUDE_LINE: &str = "include_if_exists = 'pmem.conf'";
+
+/// Server settings pmem manages for an embedded cluster. They're written to
+/// `pmem.conf` in the data directory, which `postgresql.conf` includes, so
+/// the file initdb generated stays untouched otherwise.
+#[derive(Debug, Default)]
+pub struct PgConf {
+    settings: Vec<(String, String)>,
+}
+
+impl PgConf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `name`, replacing any earlier value for it
+    pub fn set(&mut self, name: &str, value: impl Into<String>) -> &mut Self {
+        let value = value.into();
+        match self.settings.iter_mut().find(|(n, _)| n == name) {
+            Some(setting) => setting.1 = value,
+            None => self.settings.push((name.to_string(), value)),
+        }
+        self
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::from("# Managed by pmem, changes are overwritten on start\n");
+        for (name, value) in self.settings.iter() {
+            out.push_str(&format!("{} = '{}'\n", name, value.replace('\'', "''")));
+        }
+        out
+    }
+
+    pub fn write(&self, database_dir: &Path) -> anyhow::Result<()> {
+        fs::write(database_dir.join(PMEM_CONF_FILE_NAME), self.render())?;
+
+        let postgresql_conf = database_dir.join("postgresql.conf");
+        let existing = fs::read_to_string(&postgresql_conf)?;
+        if !existing.lines().any(|line| line.trim() == INCLUDE_LINE) {
+            let mut file = OpenOptions::new().append(true).open(&postgresql_conf)?;
+            writeln!(file, "\n{}", INCLUDE_LINE)?;
+        }
+        Ok(())
+    }
+}
+
+/// Who may connect and how. Unlike `pmem.conf` this replaces the file initdb
+/// generated, since it only ever allowed the method it was initialized with.
+#[derive(Debug)]
+pub struct PgHba {
+    auth_method: AuthMethod,
+    require_tls: bool,
+    client_certificates: bool,
+}
+
+impl PgHba {
+    pub fn new(auth_method: AuthMethod) -> Self {
+        Self {
+            auth_method,
+            require_tls: false,
+            client_certificates: false,
+        }
+    }
+
+    /// Only accept TCP connections over TLS, and with `client_certificates`
+    /// only from clients presenting a certificate for their role. The unix
+    /// socket is left alone.
+    pub fn require_tls(mut self, client_certificates: bool) -> Self {
+        self.require_tls = true;
+        self.client_certificates = client_certificates;
+        self
+    }
+
+    pub fn render(&self) -> String {
+        let method = self.auth_method.as_hba();
+        let host = if self.require_tls { "hostssl" } else { "host" };
+        let options = if self.client_certificates {
+            " clientcert=verify-full"
+        } else {
+            ""
+        };
+        let mut out = String::from("# Managed by pmem, changes are overwritten on start\n");
+        for database in ["all", "replication"] {
+            out.push_str(&format!("local {} all {}\n", database, method));
+            for address in ["127.0.0.1/32", "::1/128"] {
+                out.push_str(&format!(
+                    "{} {} all {} {}{}\n",
+                    host, database, address, method, options
+                ));
+            }
+        }
+        out
+    }
+
+    pub fn write(&self, database_dir: &Path) -> anyhow::Result<()> {
+        fs::write(database_dir.join(PG_HBA_FILE_NAME), self.render())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_pg_conf_quotes_values_and_overrides_earlier_ones() {
+        let mut conf = PgConf::new();
+        conf.set("logging_collector", "on")
+            .set("log_line_prefix", "it's %m ")
+            .set("logging_collector", "off");
+        assert_eq!(
+            conf.render(),
+            "# Managed by pmem, changes are overwritten on start\n\
+             logging_collector = 'off'\n\
+             log_line_prefix = 'it''s %m '\n"
+        );
+    }
+
+    #[test]
+    fn test_pg_conf_includes_itself_once() {
+        let dir = TempDir::new("pg_conf").unwrap();
+        fs::write(dir.path().join("postgresql.conf"), "port = 5432\n").unwrap();
+
+        let conf = PgConf::new();
+        conf.write(dir.path()).unwrap();
+        conf.write(dir.path()).unwrap();
+
+        let postgresql_conf = fs::read_to_string(dir.path().join("postgresql.conf")).unwrap();
+        assert_eq!(postgresql_conf.matches(INCLUDE_LINE).count(), 1);
+        assert!(dir.path().join(PMEM_CONF_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn test_pg_hba_uses_the_configured_method_everywhere() {
+        let hba = PgHba::new(AuthMethod::ScramSha256).render();
+        let rules: Vec<&str> = hba.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(rules.len(), 6);
+        assert!(rules.iter().all(|l| l.ends_with(" scram-sha-256")));
+        assert!(rules.contains(&"host replication all 127.0.0.1/32 scram-sha-256"));
+        assert!(PgHba::new(AuthMethod::Trust)
+            .render()
+            .contains("local all all trust\n"));
+    }
+
+    #[test]
+    fn test_pg_hba_can_require_tls_and_client_certificates() {
+        let hba = PgHba::new(AuthMethod::Md5).require_tls(true).render();
+        assert!(hba.contains("local all all md5\n"));
+        assert!(hba.contains("hostssl all all ::1/128 md5 clientcert=verify-full\n"));
+        assert!(!hba.contains("host "));
+    }
+}
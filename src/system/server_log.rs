@@ -0,0 +1,344 @@
+use std::{
+    fs::{self, File},
+    future::Future,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+/// Where the logging collector writes, relative to the data directory
+pub const SERVER_LOG_DIRECTORY: &str = "log";
+pub const SERVER_LOG_FILE_NAME: &str = "postgresql.log";
+/// Epoch, timestamp, pid and database in front of every line so entries can
+/// be parsed back out of the file
+pub const LOG_LINE_PREFIX: &str = "%n|%m|%p|%d|";
+/// How many lines of server log end up in a failed start's error
+pub const START_ERROR_LOG_LINES: usize = 20;
+
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Severities from least to most important. This is the order clients see
+/// them in (`client_min_messages`), so asking for errors doesn't also return
+/// every LOG line like the server's own ordering would.
+const LEVELS: [&str; 12] = [
+    "DEBUG5", "DEBUG4", "DEBUG3", "DEBUG2", "DEBUG1", "INFO", "LOG", "NOTICE", "WARNING", "ERROR",
+    "FATAL", "PANIC",
+];
+/// Lines that belong to the entry right before them
+const DETAIL_LEVELS: [&str; 6] = [
+    "DETAIL",
+    "HINT",
+    "CONTEXT",
+    "STATEMENT",
+    "QUERY",
+    "LOCATION",
+];
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ServerLogEntry {
+    /// Milliseconds since the unix epoch
+    pub time: f64,
+    pub timestamp: String,
+    pub pid: u32,
+    pub database: Option<String>,
+    pub level: String,
+    pub message: String,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ServerLogFilter {
+    /// Only entries logged at or after this many milliseconds since the epoch
+    pub since: Option<f64>,
+    /// Minimum severity, `WARNING` also returns `ERROR`, `FATAL` and `PANIC`
+    pub level: Option<String>,
+    pub database: Option<String>,
+}
+
+impl ServerLogFilter {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(level) = &self.level {
+            if severity(level).is_none() {
+                bail!(
+                    "Unknown log level {:?}, expected one of {}",
+                    level,
+                    LEVELS.join(", ")
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, entry: &ServerLogEntry) -> bool {
+        if let Some(since) = self.since {
+            if entry.time < since {
+                return false;
+            }
+        }
+        if let Some(level) = self.level.as_deref().and_then(severity) {
+            if severity(&entry.level).map(|s| s < level).unwrap_or(true) {
+                return false;
+            }
+        }
+        if let Some(database) = &self.database {
+            if entry.database.as_ref() != Some(database) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn severity(level: &str) -> Option<usize> {
+    LEVELS.iter().position(|l| l.eq_ignore_ascii_case(level))
+}
+
+/// Turns log lines into entries. An entry is only complete once the next one
+/// starts (or the caller flushes), since DETAIL/STATEMENT lines and multi-line
+/// messages follow it.
+#[derive(Debug, Default)]
+pub struct LogParser {
+    current: Option<ServerLogEntry>,
+}
+
+impl LogParser {
+    pub fn push_line(&mut self, line: &str) -> Option<ServerLogEntry> {
+        match parse_line(line) {
+            Some(entry) if DETAIL_LEVELS.contains(&entry.level.as_str()) => {
+                match self.current.as_mut() {
+                    Some(current) => {
+                        current
+                            .message
+                            .push_str(&format!("\n{}:  {}", entry.level, entry.message));
+                        None
+                    }
+                    None => self.current.replace(entry),
+                }
+            }
+            Some(entry) => self.current.replace(entry),
+            None => {
+                if let Some(current) = self.current.as_mut() {
+                    current.message.push('\n');
+                    current.message.push_str(line);
+                }
+                None
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> Option<ServerLogEntry> {
+        self.current.take()
+    }
+}
+
+fn parse_line(line: &str) -> Option<ServerLogEntry> {
+    let mut parts = line.splitn(5, '|');
+    let epoch: f64 = parts.next()?.parse().ok()?;
+    let timestamp = parts.next()?.to_string();
+    let pid = parts.next()?.parse().ok()?;
+    let database = Some(parts.next()?.to_string()).filter(|d| !d.is_empty());
+    let (level, message) = parts.next()?.split_once(":  ")?;
+    if severity(level).is_none() && !DETAIL_LEVELS.contains(&level) {
+        return None;
+    }
+
+    Some(ServerLogEntry {
+        time: (epoch * 1000.0).round(),
+        timestamp,
+        pid,
+        database,
+        level: level.to_string(),
+        message: message.to_string(),
+    })
+}
+
+pub fn parse(contents: &str) -> Vec<ServerLogEntry> {
+    let mut parser = LogParser::default();
+    let mut entries: Vec<ServerLogEntry> = contents
+        .lines()
+        .filter_map(|l| parser.push_line(l))
+        .collect();
+    entries.extend(parser.flush());
+    entries
+}
+
+/// Every entry in the log file matching `filter`
+pub fn read(path: &Path, filter: &ServerLogFilter) -> anyhow::Result<Vec<ServerLogEntry>> {
+    filter.validate()?;
+    let contents = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(parse(&contents)
+        .into_iter()
+        .filter(|e| filter.matches(e))
+        .collect())
+}
+
+/// The last `n` raw lines of the log file
+pub fn tail(path: &Path, n: usize) -> Vec<String> {
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    let lines: Vec<&str> = contents.lines().collect();
+    lines[lines.len().saturating_sub(n)..]
+        .iter()
+        .map(|l| l.to_string())
+        .collect()
+}
+
+/// Append the tail of the server log to an error message, it usually says
+/// what actually went wrong
+pub fn with_tail(message: String, path: &Path) -> String {
+    let lines = tail(path, START_ERROR_LOG_LINES);
+    if lines.is_empty() {
+        message
+    } else {
+        format!(
+            "{}\n\nLast server log lines:\n{}",
+            message,
+            lines.join("\n")
+        )
+    }
+}
+
+/// Hand every entry written to the log from now on to `on_entry`, until
+/// `is_alive` says the owner is gone. Where "now" starts is decided when this
+/// is called, not when the returned future is first polled.
+pub fn follow(
+    path: PathBuf,
+    is_alive: impl Fn() -> bool,
+    mut on_entry: impl FnMut(ServerLogEntry),
+) -> impl Future<Output = ()> {
+    let mut offset = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+
+    async move {
+        let mut partial = String::new();
+        let mut parser = LogParser::default();
+        let mut interval = tokio::time::interval(FOLLOW_INTERVAL);
+
+        while is_alive() {
+            interval.tick().await;
+            let chunk = match read_from(&path, &mut offset) {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    log::trace!("Unable to read server log: {:?}", e.to_string());
+                    continue;
+                }
+            };
+
+            if chunk.is_empty() {
+                // Nothing else is coming for the entry we're holding on to
+                if let Some(entry) = parser.flush() {
+                    on_entry(entry);
+                }
+                continue;
+            }
+
+            partial.push_str(&chunk);
+            while let Some(idx) = partial.find('\n') {
+                let line: String = partial.drain(..=idx).collect();
+                if let Some(entry) = parser.push_line(line.trim_end_matches('\n')) {
+                    on_entry(entry);
+                }
+            }
+        }
+    }
+}
+
+fn read_from(path: &Path, offset: &mut u64) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    if len < *offset {
+        // Truncated underneath us, start over
+        *offset = 0;
+    }
+    file.seek(SeekFrom::Start(*offset))?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    *offset += buf.len() as u64;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LOG: &str = "\
+1668460527.348|2022-11-14 18:35:27.348 UTC|100||LOG:  database system is ready to accept connections
+1668460528.001|2022-11-14 18:35:28.001 UTC|101|app|ERROR:  relation \"missing\" does not exist at character 15
+1668460528.001|2022-11-14 18:35:28.001 UTC|101|app|STATEMENT:  SELECT * FROM missing
+	WHERE id = 1
+1668460529.500|2022-11-14 18:35:29.500 UTC|102|other|WARNING:  there is no transaction in progress
+";
+
+    #[test]
+    fn test_parse_attaches_detail_and_continuation_lines() {
+        let entries = parse(LOG);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].database, None);
+        assert_eq!(entries[0].time, 1668460527348.0);
+        assert_eq!(entries[1].pid, 101);
+        assert_eq!(entries[1].level, "ERROR");
+        assert_eq!(
+            entries[1].message,
+            "relation \"missing\" does not exist at character 15\nSTATEMENT:  SELECT * FROM missing\n\tWHERE id = 1"
+        );
+    }
+
+    #[test]
+    fn test_filter_by_level_database_and_time() {
+        let entries = parse(LOG);
+        let count = |filter: ServerLogFilter| entries.iter().filter(|e| filter.matches(e)).count();
+
+        assert_eq!(count(ServerLogFilter::default()), 3);
+        assert_eq!(
+            count(ServerLogFilter {
+                level: Some("error".to_string()),
+                ..Default::default()
+            }),
+            1
+        );
+        assert_eq!(
+            count(ServerLogFilter {
+                level: Some("warning".to_string()),
+                ..Default::default()
+            }),
+            2
+        );
+        assert_eq!(
+            count(ServerLogFilter {
+                database: Some("app".to_string()),
+                ..Default::default()
+            }),
+            1
+        );
+        assert_eq!(
+            count(ServerLogFilter {
+                since: Some(1668460528001.0),
+                ..Default::default()
+            }),
+            2
+        );
+        assert!(ServerLogFilter {
+            level: Some("loud".to_string()),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_parser_holds_an_entry_until_the_next_one_starts() {
+        let mut parser = LogParser::default();
+        let mut lines = LOG.lines();
+        assert_eq!(parser.push_line(lines.next().unwrap()), None);
+        let first = parser.push_line(lines.next().unwrap()).unwrap();
+        assert_eq!(first.pid, 100);
+        assert_eq!(parser.push_line(lines.next().unwrap()), None);
+        assert_eq!(parser.flush().unwrap().level, "ERROR");
+        assert_eq!(parser.flush(), None);
+    }
+}
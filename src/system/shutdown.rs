@@ -0,0 +1,209 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use super::{state, utils::deserialize_optional_datetime_from_sec};
+
+/// How long `pg_ctl` gets to bring a cluster down before we give up on it
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How a cluster is brought down, from the most to the least polite. `Kill`
+/// isn't a postgres shutdown mode, it's what we resort to when even an
+/// immediate shutdown doesn't finish in time.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum StopMode {
+    /// Wait for every client to disconnect
+    Smart,
+    /// Disconnect clients and roll back their transactions
+    Fast,
+    /// Quit without a shutdown checkpoint, recovery runs on the next start
+    Immediate,
+    /// SIGKILL the postmaster and its children
+    Kill,
+}
+
+impl StopMode {
+    fn as_pg_ctl(&self) -> Option<&'static str> {
+        match self {
+            StopMode::Smart => Some("smart"),
+            StopMode::Fast => Some("fast"),
+            StopMode::Immediate => Some("immediate"),
+            StopMode::Kill => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct StopOptions {
+    /// The mode to start out with, later ones are tried once it takes too long
+    pub mode: StopMode,
+    /// Seconds until the server has to be gone, whatever it takes
+    #[serde(deserialize_with = "deserialize_optional_datetime_from_sec")]
+    pub timeout: Option<Duration>,
+}
+
+impl Default for StopOptions {
+    fn default() -> Self {
+        Self {
+            mode: StopMode::Fast,
+            timeout: Some(SHUTDOWN_TIMEOUT),
+        }
+    }
+}
+
+/// Every embedded cluster that is currently running, keyed by its database
+/// directory and pointing at the `pg_ctl` binary that can stop it.
+static RUNNING_CLUSTERS: Lazy<Mutex<HashMap<PathBuf, PathBuf>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub fn register(pg_ctl: PathBuf, database_dir: PathBuf) {
+    log::debug!(target: "pmem:shutdown", "Tracking cluster at {:?}", database_dir);
+    RUNNING_CLUSTERS
+        .lock()
+        .unwrap()
+        .insert(database_dir, pg_ctl);
+}
+
+pub fn unregister(database_dir: &Path) {
+    RUNNING_CLUSTERS.lock().unwrap().remove(database_dir);
+}
+
+static REMOVE_ON_EXIT: Lazy<Mutex<Vec<PathBuf>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Remove `path` once `stop_all` has stopped the clusters that might use it
+pub fn remove_on_exit(path: PathBuf) {
+    REMOVE_ON_EXIT.lock().unwrap().push(path);
+}
+
+pub fn forget_removal(path: &Path) {
+    REMOVE_ON_EXIT.lock().unwrap().retain(|p| p != path);
+}
+
+/// Stop a single cluster with a fast shutdown, waiting at most `timeout`.
+///
+/// This is synchronous on purpose: it runs from signal handlers, the node
+/// `exit` hook and finalizers, none of which can drive a future.
+pub fn stop_cluster(pg_ctl: &Path, database_dir: &Path, timeout: Duration) -> anyhow::Result<()> {
+    let options = StopOptions {
+        mode: StopMode::Fast,
+        timeout: Some(timeout),
+    };
+    stop_cluster_with(pg_ctl, database_dir, &options).map(|_| ())
+}
+
+/// Stop a cluster starting with `options.mode`. Every mode gets an equal
+/// share of the time that's left, once it runs out we move on to the next
+/// one, and past the deadline the postmaster is killed. Returns the mode
+/// that got the server down.
+pub fn stop_cluster_with(
+    pg_ctl: &Path,
+    database_dir: &Path,
+    options: &StopOptions,
+) -> anyhow::Result<StopMode> {
+    let pid = match state::postmaster_pid(database_dir) {
+        Some(pid) if state::is_postgres_running(pid) => pid,
+        _ => {
+            unregister(database_dir);
+            anyhow::bail!("No server running in {:?}", database_dir);
+        }
+    };
+
+    let timeout = options.timeout.unwrap_or(SHUTDOWN_TIMEOUT);
+    let deadline = Instant::now() + timeout;
+    let modes: Vec<StopMode> = [StopMode::Smart, StopMode::Fast, StopMode::Immediate]
+        .iter()
+        .copied()
+        .filter(|m| *m >= options.mode)
+        .collect();
+
+    for (i, mode) in modes.iter().enumerate() {
+        let budget = deadline.saturating_duration_since(Instant::now()) / (modes.len() - i) as u32;
+        log::debug!(target: "pmem:shutdown", "Trying a {:?} shutdown for {:?}", mode, budget);
+        signal_stop(pg_ctl, database_dir, *mode)?;
+        if wait_for_exit(pid, budget) {
+            unregister(database_dir);
+            return Ok(*mode);
+        }
+        log::warn!(
+            target: "pmem:shutdown",
+            "{:?} shutdown of {:?} didn't finish within {:?}",
+            mode,
+            database_dir,
+            budget
+        );
+    }
+
+    log::warn!(target: "pmem:shutdown", "Killing postmaster {}", pid);
+    state::kill_process_tree(pid);
+    if !wait_for_exit(pid, SHUTDOWN_TIMEOUT) {
+        anyhow::bail!("Postmaster {} survived SIGKILL", pid);
+    }
+    // Nobody got to clean up after it
+    state::remove_postmaster_pid(database_dir);
+    unregister(database_dir);
+    Ok(StopMode::Kill)
+}
+
+/// Ask the postmaster to shut down in `mode` without waiting for it
+fn signal_stop(pg_ctl: &Path, database_dir: &Path, mode: StopMode) -> anyhow::Result<()> {
+    let mode = match mode.as_pg_ctl() {
+        Some(mode) => mode,
+        None => return Ok(()),
+    };
+    let status = Command::new(pg_ctl)
+        .args(["stop", "-m", mode, "-W", "-D"])
+        .arg(database_dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+
+    // The server going away between our check and pg_ctl's is fine
+    if status.success() || state::postmaster_pid(database_dir).is_none() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "pg_ctl stop exited with {} for {:?}",
+            status,
+            database_dir
+        ))
+    }
+}
+
+fn wait_for_exit(pid: u32, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if !state::is_postgres_running(pid) {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(STOP_POLL_INTERVAL);
+    }
+}
+
+/// Stop every cluster started by this process
+pub fn stop_all() {
+    let clusters: Vec<(PathBuf, PathBuf)> = RUNNING_CLUSTERS.lock().unwrap().drain().collect();
+    for (database_dir, pg_ctl) in clusters {
+        log::info!(target: "pmem:shutdown", "Stopping cluster at {:?}", database_dir);
+        if let Err(e) = stop_cluster(&pg_ctl, &database_dir, SHUTDOWN_TIMEOUT) {
+            log::error!(target: "pmem:shutdown", "Unable to stop cluster: {:?}", e.to_string());
+        }
+    }
+    let paths: Vec<PathBuf> = REMOVE_ON_EXIT.lock().unwrap().drain(..).collect();
+    for path in paths {
+        log::info!(target: "pmem:shutdown", "Removing {:?}", path);
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,156 @@
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sysinfo::{
+    Pid, PidExt, ProcessExt, ProcessRefreshKind, ProcessStatus, RefreshKind, System, SystemExt,
+};
+
+pub const STATE_FILE_NAME: &str = "pmem.state.json";
+const POSTMASTER_PID_FILE_NAME: &str = "postmaster.pid";
+
+/// What a reusable cluster leaves behind in its `root_path` so the next run
+/// can find it and connect with the same credentials
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ClusterState {
+    pub pid: u32,
+    pub port: i16,
+    pub username: String,
+    pub password: String,
+    pub version: String,
+}
+
+impl ClusterState {
+    pub fn path(root_path: &Path) -> PathBuf {
+        root_path.join(STATE_FILE_NAME)
+    }
+
+    pub fn read(root_path: &Path) -> Option<ClusterState> {
+        let contents = fs::read_to_string(Self::path(root_path)).ok()?;
+        match serde_json::from_str(&contents) {
+            Ok(state) => Some(state),
+            Err(e) => {
+                log::warn!("Ignoring unreadable state file: {:?}", e.to_string());
+                None
+            }
+        }
+    }
+
+    /// Only readable by us, it holds the password
+    pub fn write(&self, root_path: &Path) -> anyhow::Result<()> {
+        let path = Self::path(root_path);
+        // The mode only applies to a file that's created
+        let _ = fs::remove_file(&path);
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(&path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn remove(root_path: &Path) {
+        let _ = fs::remove_file(Self::path(root_path));
+    }
+}
+
+/// The postmaster pid recorded in the data directory, if there is one
+pub fn postmaster_pid(database_dir: &Path) -> Option<u32> {
+    let contents = fs::read_to_string(database_dir.join(POSTMASTER_PID_FILE_NAME)).ok()?;
+    contents.lines().next()?.trim().parse().ok()
+}
+
+pub fn remove_postmaster_pid(database_dir: &Path) {
+    let _ = fs::remove_file(database_dir.join(POSTMASTER_PID_FILE_NAME));
+}
+
+/// Is `pid` a live postgres process? A pid that has been recycled by some
+/// other program counts as dead.
+pub fn is_postgres_running(pid: u32) -> bool {
+    let pid = Pid::from_u32(pid);
+    let mut s = System::new_with_specifics(RefreshKind::new());
+    if !s.refresh_process_specifics(pid, ProcessRefreshKind::new()) {
+        return false;
+    }
+    s.process(pid)
+        .map(|p| p.name().contains("postgres") && p.status() != ProcessStatus::Zombie)
+        .unwrap_or(false)
+}
+
+/// Force a process down when `pg_ctl` couldn't do it for us
+pub fn kill_process(pid: u32) -> bool {
+    let pid = Pid::from_u32(pid);
+    let mut s = System::new_with_specifics(RefreshKind::new());
+    s.refresh_process_specifics(pid, ProcessRefreshKind::new());
+    s.process(pid).map(|p| p.kill()).unwrap_or(false)
+}
+
+/// Kill a postmaster along with its backends, which would otherwise linger
+/// until they notice it's gone
+pub fn kill_process_tree(pid: u32) -> bool {
+    let parent = Pid::from_u32(pid);
+    let s =
+        System::new_with_specifics(RefreshKind::new().with_processes(ProcessRefreshKind::new()));
+    for child in s
+        .processes()
+        .values()
+        .filter(|p| p.parent() == Some(parent))
+    {
+        child.kill();
+    }
+    s.process(parent).map(|p| p.kill()).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_state_round_trips_through_the_root_path() {
+        let dir = TempDir::new("state").unwrap();
+        assert_eq!(ClusterState::read(dir.path()), None);
+
+        let state = ClusterState {
+            pid: 42,
+            port: 5433,
+            username: "postgres".to_string(),
+            password: "secret".to_string(),
+            version: "14.3.0".to_string(),
+        };
+        state.write(dir.path()).unwrap();
+        assert_eq!(ClusterState::read(dir.path()), Some(state));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(ClusterState::path(dir.path()))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        ClusterState::remove(dir.path());
+        assert_eq!(ClusterState::read(dir.path()), None);
+    }
+
+    #[test]
+    fn test_postmaster_pid_reads_the_first_line() {
+        let dir = TempDir::new("state").unwrap();
+        assert_eq!(postmaster_pid(dir.path()), None);
+        fs::write(
+            dir.path().join(POSTMASTER_PID_FILE_NAME),
+            "1234\n/tmp/db\n1668460527\n5433\n",
+        )
+        .unwrap();
+        assert_eq!(postmaster_pid(dir.path()), Some(1234));
+    }
+}
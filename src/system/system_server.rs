@@ -7,8 +7,13 @@ use neon::{prelude::*, types::Deferred};
 use tokio::runtime::Handle;
 use tracing::*;
 
-use crate::system::config::ConfigDatabase;
+use crate::system::config::{ConfigBuilder, ConfigDatabase};
 
+use super::create_options::CreateOptions;
+use super::logger;
+use super::redact::{redact, Redacted};
+use super::server_log::{self, ServerLogFilter};
+use super::shutdown::{self, StopOptions};
 use super::system::System;
 use super::utils::{block_on, runtime};
 
@@ -37,7 +42,52 @@ pub struct SystemServer {
     tx: tokio::sync::mpsc::Sender<SystemMessage>,
 }
 
-impl Finalize for SystemServer {}
+impl Finalize for SystemServer {
+    // Called when JS garbage collects the boxed server (or the environment is
+    // torn down) without anyone calling `stop`
+    fn finalize<'a, C: Context<'a>>(self, _cx: &mut C) {
+        log::debug!(target: "pmem:system_server", "Finalizing system server");
+        let _ = self.tx.try_send(SystemMessage::Terminate);
+    }
+}
+
+/// The options given, over pmem.toml and the PMEM_* variables, over the JS
+/// defaults
+fn js_config(
+    options: &serde_json::Value,
+    defaults: &serde_json::Value,
+) -> anyhow::Result<ConfigDatabase> {
+    let builder = ConfigBuilder::default().merge_json(defaults)?.load()?;
+    Ok(builder.merge_json(options)?.build())
+}
+
+/// The database name in the first argument, `None` when it's left out
+fn js_db_name(cx: &mut FunctionContext) -> NeonResult<Option<String>> {
+    match cx.argument_opt(0) {
+        Some(v) if !v.is_a::<JsUndefined, _>(cx) && !v.is_a::<JsNull, _>(cx) => {
+            match v.downcast::<JsString, _>(cx) {
+                Ok(name) => Ok(Some(name.value(cx))),
+                Err(_) => cx.throw_type_error("The database name must be a string"),
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The `CreateOptions` in the second argument, checked as far as they can be
+/// without the server. The server's version is checked on creation.
+fn js_create_options(cx: &mut FunctionContext) -> NeonResult<CreateOptions> {
+    let options: CreateOptions = match cx.argument_opt(1) {
+        Some(v) if !v.is_a::<JsUndefined, _>(cx) && !v.is_a::<JsNull, _>(cx) => {
+            neon_serde3::from_value(cx, v).or_else(|e| cx.throw_error(e.to_string()))?
+        }
+        _ => CreateOptions::default(),
+    };
+    options
+        .validate(None)
+        .or_else(|e| cx.throw_error(e.to_string()))?;
+    Ok(options)
+}
 
 impl SystemServer {
     fn new<'a, C>(cx: &mut C, config_database: ConfigDatabase) -> anyhow::Result<Self>
@@ -47,11 +97,14 @@ impl SystemServer {
         // First get the database -- make this configurable, maybe?
         // let db = DBType::default();
         let (tx, mut rx) = tokio::sync::mpsc::channel::<SystemMessage>(32);
-        // let signal_tx = tx.clone();
         let channel = cx.channel();
 
         let rt = runtime(cx).unwrap(); //.unwrap_or_else(|err| anyhow::anyhow!(err.to_string()));
-        let system = rt.block_on(async move { System::initialize(config_database).await.unwrap() });
+        if let Err(e) = logger::init_logging(&config_database.log_config()) {
+            // Someone else's logger is already installed
+            log::debug!("Unable to initialize logging: {:?}", e.to_string());
+        }
+        let system = rt.block_on(System::initialize(config_database))?;
         // We need a channel for communication back to JS
         let mut sys = Arc::new(Mutex::new(system));
 
@@ -65,40 +118,28 @@ impl SystemServer {
                         }
                         SystemMessage::Close(deferred, f) => {
                             log::debug!(target: "pmem:system_server", "Closing handle here");
+                            // The callback stops the system the way it was asked to,
+                            // this only makes sure it's down before we go away
+                            f(&mut sys, &channel, deferred);
                             let handle = Handle::current();
                             let _ = handle.enter();
-                            let res = futures::executor::block_on(sys.clone().lock().unwrap().stop());
+                            let res = futures::executor::block_on(sys.clone().lock().unwrap().stop(&StopOptions::default()));
                             log::debug!(target: "pmem:system_server", "Result from stop: {:?}", res);
-                            f(&mut sys, &channel, deferred);
                             return;
                         }
                         SystemMessage::Terminate => {
-                            println!("Breaking from Terminate");
                             log::trace!(target: "pmem:system_server", "Terminate called");
-                            let handle = Handle::current();
-                            let _ = handle.enter();
-                            println!("Breaking from Terminate in handler");
-                            let _res = futures::executor::block_on(sys.clone().lock().unwrap().stop());
-                            log::debug!(target: "pmem:system_server", "Breaking from Terminate");
+                            let res = sys.clone().lock().unwrap().shutdown();
+                            log::debug!(target: "pmem:system_server", "Result from shutdown: {:?}", res);
                             return;
                         }
                     }
                 }
-                // TODO: handle signals
-                // _res = tokio::signal::ctrl_c() => {
-                //     println!("Breaking from Terminate");
-                //     let handle = Handle::current();
-                //     let _ = handle.enter();
-                //     println!("Breaking from Terminate");
-                //     let _res = futures::executor::block_on(signal_tx.send(SystemMessage::Terminate));
-                //     println!("Breaking from Terminate");
-                //     // drop(sys);
-                //     // let _ = signal_tx.send(SystemMessage::Terminate);
-                //     log::debug!(target: "pmem:system_server", "Received a control+c");
-                //     break;
-                // }
                 else => {
-                    log::debug!("Else?");
+                    // Every sender is gone, so nobody can ask us to stop anymore
+                    log::debug!(target: "pmem:system_server", "Channel closed, shutting down");
+                    let _ = sys.clone().lock().unwrap().shutdown();
+                    return;
                 }
             }
         }
@@ -137,12 +178,19 @@ impl SystemServer {
 
 impl SystemServer {
     pub fn js_init(mut cx: FunctionContext) -> JsResult<JsBox<SystemServer>> {
-        let cfg = cx.argument::<JsValue>(0)?;
-
-        let config_database: ConfigDatabase = neon_serde3::from_value(&mut cx, cfg).unwrap();
+        let options = cx.argument::<JsValue>(0)?;
+        let options: serde_json::Value =
+            neon_serde3::from_value(&mut cx, options).or_else(|e| cx.throw_error(e.to_string()))?;
+        let defaults: serde_json::Value = match cx.argument_opt(1) {
+            Some(defaults) => neon_serde3::from_value(&mut cx, defaults)
+                .or_else(|e| cx.throw_error(e.to_string()))?,
+            None => serde_json::Value::Null,
+        };
+        let config_database = js_config(&options, &defaults)
+            .or_else(|err| cx.throw_error(redact(&format!("{:#}", err))))?;
 
         let system_server = SystemServer::new(&mut cx, config_database)
-            .or_else(|err| cx.throw_error(err.to_string()))?;
+            .or_else(|err| cx.throw_error(redact(&err.to_string())))?;
         debug!("Got a system server handle");
 
         Ok(cx.boxed(system_server))
@@ -165,7 +213,11 @@ impl SystemServer {
                 log::trace!("In start: {:?}", res);
                 // new Promise((resolve) => resolve())
                 deferred.settle_with(channel, move |mut cx| -> JsResult<JsBoolean> {
-                    Ok(cx.boolean(true))
+                    match res {
+                        // Carries the tail of the server log when the server didn't come up
+                        Err(e) => cx.throw_error(redact(&e.to_string())),
+                        Ok(_) => Ok(cx.boolean(true)),
+                    }
                 });
             })
             .into_rejection(&mut cx)?;
@@ -179,18 +231,66 @@ impl SystemServer {
             .this()
             .downcast_or_throw::<JsBox<SystemServer>, _>(&mut cx)?;
 
+        let options: StopOptions = match cx.argument_opt(0) {
+            Some(v) if !v.is_a::<JsUndefined, _>(&mut cx) && !v.is_a::<JsNull, _>(&mut cx) => {
+                neon_serde3::from_value(&mut cx, v).or_else(|e| cx.throw_error(e.to_string()))?
+            }
+            _ => StopOptions::default(),
+        };
+
         system_server
             .close(deferred, move |sys, channel, deferred| {
                 let mut sys = sys.lock().unwrap();
                 log::info!("Close called");
                 let handle = Handle::current();
                 let _ = handle.enter();
-                let res = futures::executor::block_on(sys.stop());
+                let res = futures::executor::block_on(sys.stop(&options));
 
-                deferred.settle_with(channel, move |mut cx| -> JsResult<JsString> {
+                // Resolves to the mode that stopped the server, null if there
+                // was nothing to stop
+                deferred.settle_with(channel, move |mut cx| -> JsResult<JsValue> {
+                    match res {
+                        Err(e) => cx.throw_error(redact(&e.to_string())),
+                        Ok(None) => Ok(cx.null().upcast()),
+                        Ok(Some(mode)) => neon_serde3::to_value(&mut cx, &mode)
+                            .or_else(|e| cx.throw_error(e.to_string())),
+                    }
+                });
+            })
+            .into_rejection(&mut cx)?;
+
+        Ok(promise)
+    }
+
+    /// Synchronously stop every running cluster, used from node's `exit` hook
+    /// and signal handlers
+    pub fn js_shutdown_all(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        shutdown::stop_all();
+        Ok(cx.undefined())
+    }
+
+    pub fn js_server_logs(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let (deferred, promise) = cx.promise();
+        let system_server = cx
+            .this()
+            .downcast_or_throw::<JsBox<SystemServer>, _>(&mut cx)?;
+
+        let filter: ServerLogFilter = match cx.argument_opt(0) {
+            Some(v) if !v.is_a::<JsUndefined, _>(&mut cx) && !v.is_a::<JsNull, _>(&mut cx) => {
+                neon_serde3::from_value(&mut cx, v).or_else(|e| cx.throw_error(e.to_string()))?
+            }
+            _ => ServerLogFilter::default(),
+        };
+
+        system_server
+            .send(deferred, move |sys, channel, deferred| {
+                let res = sys.lock().unwrap().server_logs(&filter);
+
+                deferred.settle_with(channel, move |mut cx| -> JsResult<JsValue> {
                     match res {
-                        Err(e) => Ok(cx.string(e.to_string())),
-                        Ok(_) => Ok(cx.string("Closed")),
+                        Err(e) => cx.throw_error(redact(&e.to_string())),
+                        Ok(entries) => neon_serde3::to_value(&mut cx, &entries)
+                            .or_else(|e| cx.throw_error(e.to_string())),
                     }
                 });
             })
@@ -199,22 +299,117 @@ impl SystemServer {
         Ok(promise)
     }
 
+    /// The CA (and client certificate) paths of a TLS cluster, or null
+    pub fn js_tls_files(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let (deferred, promise) = cx.promise();
+        let system_server = cx
+            .this()
+            .downcast_or_throw::<JsBox<SystemServer>, _>(&mut cx)?;
+
+        system_server
+            .send(deferred, move |sys, channel, deferred| {
+                let files = sys.lock().unwrap().tls_files();
+
+                deferred.settle_with(channel, move |mut cx| -> JsResult<JsValue> {
+                    match files {
+                        None => Ok(cx.null().upcast()),
+                        Some(files) => neon_serde3::to_value(&mut cx, &files)
+                            .or_else(|e| cx.throw_error(e.to_string())),
+                    }
+                });
+            })
+            .into_rejection(&mut cx)?;
+
+        Ok(promise)
+    }
+
+    /// Call the given function with every new server log entry until the
+    /// system goes away
+    pub fn js_on_server_log(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let (deferred, promise) = cx.promise();
+        let system_server = cx
+            .this()
+            .downcast_or_throw::<JsBox<SystemServer>, _>(&mut cx)?;
+
+        let callback = Arc::new(cx.argument::<JsFunction>(0)?.root(&mut cx));
+        // Following the log shouldn't keep node alive
+        let mut log_channel = cx.channel();
+        log_channel.unref(&mut cx);
+
+        system_server
+            .send(deferred, move |sys, channel, deferred| {
+                let res = sys.lock().unwrap().server_log_path();
+                if let Ok(path) = &res {
+                    let system = Arc::downgrade(sys);
+                    tokio::spawn(server_log::follow(
+                        path.clone(),
+                        move || system.strong_count() > 0,
+                        move |entry| {
+                            let callback = callback.clone();
+                            log_channel.send(move |mut cx| {
+                                let this = cx.undefined();
+                                let arg = neon_serde3::to_value(&mut cx, &entry)
+                                    .or_else(|e| cx.throw_error(e.to_string()))?;
+                                callback.to_inner(&mut cx).call(&mut cx, this, vec![arg])?;
+                                Ok(())
+                            });
+                        },
+                    ));
+                }
+
+                deferred.settle_with(channel, move |mut cx| -> JsResult<JsBoolean> {
+                    match res {
+                        Err(e) => cx.throw_error(redact(&e.to_string())),
+                        Ok(_) => Ok(cx.boolean(true)),
+                    }
+                });
+            })
+            .into_rejection(&mut cx)?;
+
+        Ok(promise)
+    }
+
+    /// Call the given function with every record pmem logs, from any system
+    pub fn js_on_log(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let callback = Arc::new(cx.argument::<JsFunction>(0)?.root(&mut cx));
+        let mut log_channel = cx.channel();
+        log_channel.unref(&mut cx);
+
+        logger::add_listener(move |record| {
+            let callback = callback.clone();
+            let record = record.clone();
+            // Records keep coming while node tears down, there's nobody to
+            // tell by then
+            let _ = log_channel.try_send(move |mut cx| {
+                let this = cx.undefined();
+                let arg = neon_serde3::to_value(&mut cx, &record)
+                    .or_else(|e| cx.throw_error(e.to_string()))?;
+                callback.to_inner(&mut cx).call(&mut cx, this, vec![arg])?;
+                Ok(())
+            });
+        });
+        Ok(cx.undefined())
+    }
+
     pub fn js_create_new_db(mut cx: FunctionContext) -> JsResult<JsPromise> {
         let (deferred, promise) = cx.promise();
         let system_server = cx
             .this()
             .downcast_or_throw::<JsBox<SystemServer>, _>(&mut cx)?;
 
+        let name = js_db_name(&mut cx)?;
+        let options = js_create_options(&mut cx)?;
+
         system_server
             .send(deferred, move |sys, channel, deferred| {
                 let mut sys = sys.lock().unwrap();
                 let handle = Handle::current();
                 let _ = handle.enter();
-                let res = futures::executor::block_on(sys.create_new_db(None));
+                let res = futures::executor::block_on(sys.create_new_db(name, &options));
 
                 deferred.settle_with(channel, move |mut cx| -> JsResult<JsString> {
                     match res {
-                        Err(e) => Ok(cx.string(e.to_string())),
+                        Err(e) => cx.throw_error(redact(&e.to_string())),
                         Ok(conn_url) => Ok(cx.string(conn_url)),
                     }
                 });
@@ -224,6 +419,88 @@ impl SystemServer {
         Ok(promise)
     }
 
+    /// Like `new_db`, but resolves to the database's URIs on the primary and
+    /// on the replica
+    pub fn js_create_new_db_uris(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let (deferred, promise) = cx.promise();
+        let system_server = cx
+            .this()
+            .downcast_or_throw::<JsBox<SystemServer>, _>(&mut cx)?;
+
+        let name = js_db_name(&mut cx)?;
+        let options = js_create_options(&mut cx)?;
+
+        system_server
+            .send(deferred, move |sys, channel, deferred| {
+                let mut sys = sys.lock().unwrap();
+                let handle = Handle::current();
+                let _ = handle.enter();
+                let res = futures::executor::block_on(sys.create_new_db_uris(name, &options));
+
+                deferred.settle_with(channel, move |mut cx| -> JsResult<JsValue> {
+                    match res {
+                        Err(e) => cx.throw_error(redact(&e.to_string())),
+                        Ok(uris) => neon_serde3::to_value(&mut cx, &uris)
+                            .or_else(|e| cx.throw_error(e.to_string())),
+                    }
+                });
+            })
+            .into_rejection(&mut cx)?;
+
+        Ok(promise)
+    }
+
+    pub fn js_pause_replay(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let (deferred, promise) = cx.promise();
+        let system_server = cx
+            .this()
+            .downcast_or_throw::<JsBox<SystemServer>, _>(&mut cx)?;
+
+        system_server
+            .send(deferred, move |sys, channel, deferred| {
+                let mut sys = sys.lock().unwrap();
+                let handle = Handle::current();
+                let _ = handle.enter();
+                let res = futures::executor::block_on(sys.pause_replay());
+
+                deferred.settle_with(channel, move |mut cx| -> JsResult<JsBoolean> {
+                    match res {
+                        Err(e) => cx.throw_error(redact(&e.to_string())),
+                        Ok(_) => Ok(cx.boolean(true)),
+                    }
+                });
+            })
+            .into_rejection(&mut cx)?;
+
+        Ok(promise)
+    }
+
+    /// Resolves once the replica has caught up with the primary
+    pub fn js_resume_replay(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let (deferred, promise) = cx.promise();
+        let system_server = cx
+            .this()
+            .downcast_or_throw::<JsBox<SystemServer>, _>(&mut cx)?;
+
+        system_server
+            .send(deferred, move |sys, channel, deferred| {
+                let mut sys = sys.lock().unwrap();
+                let handle = Handle::current();
+                let _ = handle.enter();
+                let res = futures::executor::block_on(sys.resume_replay());
+
+                deferred.settle_with(channel, move |mut cx| -> JsResult<JsBoolean> {
+                    match res {
+                        Err(e) => cx.throw_error(redact(&e.to_string())),
+                        Ok(_) => Ok(cx.boolean(true)),
+                    }
+                });
+            })
+            .into_rejection(&mut cx)?;
+
+        Ok(promise)
+    }
+
     pub fn js_execute_migrations(mut cx: FunctionContext) -> JsResult<JsPromise> {
         let (deferred, promise) = cx.promise();
         let system_server = cx
@@ -233,14 +510,15 @@ impl SystemServer {
         let db_name = cx.argument::<JsString>(0)?.value(&mut cx);
         let migrations_path_str = cx.argument::<JsString>(1)?.value(&mut cx);
 
-        println!(
+        log::debug!(
             "Called js_execute_migrations: {} {}",
-            db_name, migrations_path_str
+            db_name,
+            migrations_path_str
         );
 
         system_server
             .send(deferred, move |sys, channel, deferred| {
-                let mut sys = sys.lock().unwrap();
+                let sys = sys.lock().unwrap();
                 let handle = Handle::current();
                 let _ = handle.enter();
                 let res = futures::executor::block_on(sys.migration(db_name, migrations_path_str));
@@ -273,10 +551,10 @@ impl SystemServer {
 
         system_server
             .send(deferred, move |sys, channel, deferred| {
-                let mut sys = sys.lock().unwrap();
+                let sys = sys.lock().unwrap();
                 let handle = Handle::current();
                 let _ = handle.enter();
-                let res = futures::executor::block_on(sys.execute_sql(sql));
+                let res = futures::executor::block_on(sys.execute_sql(sql, None));
 
                 deferred.settle_with(channel, move |mut cx| -> JsResult<JsBoolean> {
                     match res {
@@ -293,6 +571,90 @@ impl SystemServer {
         Ok(promise)
     }
 
+    /// Resolves to the id of a change capture session on a database
+    pub fn js_start_change_capture(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let (deferred, promise) = cx.promise();
+        let system_server = cx
+            .this()
+            .downcast_or_throw::<JsBox<SystemServer>, _>(&mut cx)?;
+
+        let db_name = cx.argument::<JsString>(0)?.value(&mut cx);
+
+        system_server
+            .send(deferred, move |sys, channel, deferred| {
+                let mut sys = sys.lock().unwrap();
+                let handle = Handle::current();
+                let _ = handle.enter();
+                let res = futures::executor::block_on(sys.start_change_capture(db_name));
+
+                deferred.settle_with(channel, move |mut cx| -> JsResult<JsString> {
+                    match res {
+                        Err(e) => cx.throw_error(redact(&e.to_string())),
+                        Ok(id) => Ok(cx.string(id)),
+                    }
+                });
+            })
+            .into_rejection(&mut cx)?;
+
+        Ok(promise)
+    }
+
+    /// Resolves to the rows changed since the session's last read
+    pub fn js_read_changes(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let (deferred, promise) = cx.promise();
+        let system_server = cx
+            .this()
+            .downcast_or_throw::<JsBox<SystemServer>, _>(&mut cx)?;
+
+        let id = cx.argument::<JsString>(0)?.value(&mut cx);
+
+        system_server
+            .send(deferred, move |sys, channel, deferred| {
+                let mut sys = sys.lock().unwrap();
+                let handle = Handle::current();
+                let _ = handle.enter();
+                let res = futures::executor::block_on(sys.read_changes(id));
+
+                deferred.settle_with(channel, move |mut cx| -> JsResult<JsValue> {
+                    match res {
+                        Err(e) => cx.throw_error(redact(&e.to_string())),
+                        Ok(changes) => neon_serde3::to_value(&mut cx, &changes)
+                            .or_else(|e| cx.throw_error(e.to_string())),
+                    }
+                });
+            })
+            .into_rejection(&mut cx)?;
+
+        Ok(promise)
+    }
+
+    pub fn js_end_change_capture(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let (deferred, promise) = cx.promise();
+        let system_server = cx
+            .this()
+            .downcast_or_throw::<JsBox<SystemServer>, _>(&mut cx)?;
+
+        let id = cx.argument::<JsString>(0)?.value(&mut cx);
+
+        system_server
+            .send(deferred, move |sys, channel, deferred| {
+                let mut sys = sys.lock().unwrap();
+                let handle = Handle::current();
+                let _ = handle.enter();
+                let res = futures::executor::block_on(sys.end_change_capture(id));
+
+                deferred.settle_with(channel, move |mut cx| -> JsResult<JsBoolean> {
+                    match res {
+                        Err(e) => cx.throw_error(redact(&e.to_string())),
+                        Ok(_) => Ok(cx.boolean(true)),
+                    }
+                });
+            })
+            .into_rejection(&mut cx)?;
+
+        Ok(promise)
+    }
+
     pub fn js_drop_database(mut cx: FunctionContext) -> JsResult<JsPromise> {
         let (deferred, promise) = cx.promise();
         let system_server = cx
@@ -301,7 +663,11 @@ impl SystemServer {
 
         let uri = cx.argument::<JsString>(0)?.value(&mut cx);
         let db_name = cx.argument::<JsString>(1)?.value(&mut cx);
-        log::debug!("Calling drop database at: {:?} for {:?}", uri, db_name);
+        log::debug!(
+            "Calling drop database at: {:?} for {:?}",
+            Redacted(&uri),
+            db_name
+        );
 
         system_server
             .send(deferred, move |sys, channel, deferred| {
@@ -0,0 +1,174 @@
+//! Support for `#[pmem::test]`, which expands to a call to `run`
+
+use std::{
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+};
+
+use once_cell::sync::{Lazy, OnceCell};
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use tokio::{runtime::Runtime, sync::Mutex};
+
+use super::{cluster::Cluster, config::ConfigDatabase, shutdown};
+
+/// The shared cluster's pools and tasks live here, tests get a runtime of
+/// their own
+static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Unable to create the pmem test runtime")
+});
+static CLUSTER: OnceCell<Mutex<Cluster>> = OnceCell::new();
+
+#[derive(Debug)]
+pub struct TestOptions {
+    pub name: &'static str,
+    pub migrations: Option<&'static str>,
+    pub keep_on_failure: bool,
+}
+
+/// What a test can take the database as
+pub trait TestArg {
+    fn from_uri(uri: &str) -> Self;
+}
+
+impl TestArg for String {
+    fn from_uri(uri: &str) -> Self {
+        uri.to_string()
+    }
+}
+
+impl TestArg for PgPool {
+    fn from_uri(uri: &str) -> Self {
+        PgPoolOptions::new()
+            .connect_lazy(uri)
+            .expect("Unable to create a pool for the test database")
+    }
+}
+
+/// What a test can return
+pub trait TestOutcome {
+    fn failed(&self) -> bool;
+}
+
+impl TestOutcome for () {
+    fn failed(&self) -> bool {
+        false
+    }
+}
+
+impl<T, E> TestOutcome for Result<T, E> {
+    fn failed(&self) -> bool {
+        self.is_err()
+    }
+}
+
+/// Create a database, run `test` against its URI on a runtime of its own
+/// and drop the database again. Must not be called from within a runtime.
+pub fn run<F, Fut>(options: TestOptions, test: F) -> Fut::Output
+where
+    F: FnOnce(String) -> Fut,
+    Fut: Future,
+    Fut::Output: TestOutcome,
+{
+    let cluster = shared_cluster();
+    let db_name = cuid::cuid().expect("Unable to generate a database name");
+    let uri = RUNTIME
+        .block_on(create_database(cluster, &db_name, options.migrations))
+        .unwrap_or_else(|e| {
+            panic!(
+                "Unable to set up the database for {}: {:?}",
+                options.name, e
+            )
+        });
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Unable to create the test runtime");
+    let result = panic::catch_unwind(AssertUnwindSafe(|| rt.block_on(test(uri.clone()))));
+    // Closes the connections the test left open
+    drop(rt);
+
+    let failed = result.as_ref().map_or(true, |outcome| outcome.failed());
+    if failed && options.keep_on_failure {
+        log::warn!(target: "pmem:test", "{} failed, keeping its database at {}", options.name, uri);
+        RUNTIME.block_on(async { cluster.lock().await.mark_failed() });
+    } else if let Err(e) = RUNTIME.block_on(drop_database(cluster, &db_name)) {
+        log::warn!(target: "pmem:test", "Unable to drop the database of {}: {:?}", options.name, e);
+    }
+    result.unwrap_or_else(|panic| panic::resume_unwind(panic))
+}
+
+/// Started by the first test, it lives until the process exits
+fn shared_cluster() -> &'static Mutex<Cluster> {
+    CLUSTER
+        .get_or_try_init(|| {
+            let config = ConfigDatabase::builder()
+                .keep_on_failure(true)
+                .load()?
+                .build();
+            let cluster = RUNTIME.block_on(Cluster::start(config))?;
+            stop_all_at_exit();
+            Ok::<_, anyhow::Error>(Mutex::new(cluster))
+        })
+        .unwrap_or_else(|e| panic!("Unable to start the pmem test cluster: {:?}", e))
+}
+
+async fn create_database(
+    cluster: &Mutex<Cluster>,
+    db_name: &str,
+    migrations: Option<&str>,
+) -> anyhow::Result<String> {
+    let mut cluster = cluster.lock().await;
+    let uri = cluster.create_database(Some(db_name)).await?;
+    if let Some(migrations) = migrations {
+        cluster.migrate(db_name, migrations).await?;
+    }
+    Ok(uri)
+}
+
+async fn drop_database(cluster: &Mutex<Cluster>, db_name: &str) -> anyhow::Result<()> {
+    let mut cluster = cluster.lock().await;
+    // Backends of connections the test just closed may still be around
+    cluster.disconnect_all(db_name).await?;
+    cluster.drop_database(db_name).await
+}
+
+/// Statics are never dropped, so the cluster is stopped on the way out
+fn stop_all_at_exit() {
+    extern "C" fn stop_all() {
+        shutdown::stop_all();
+    }
+    unsafe {
+        libc::atexit(stop_all);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sqlx::Row;
+
+    #[pmem::test(migrations = "test/fixtures/migrations")]
+    async fn test_pmem_test_runs_migrations_on_a_fresh_database(pool: sqlx::PgPool) {
+        let row = sqlx::query("SELECT email FROM public.\"User\"")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let email: String = row.get(0);
+        assert_eq!(email, "demo@company.com");
+    }
+
+    #[pmem::test]
+    async fn test_pmem_test_passes_the_uri(uri: String) -> anyhow::Result<()> {
+        let pool = sqlx::PgPool::connect(&uri).await?;
+        let tables: i64 = sqlx::query_scalar(
+            "SELECT count(*) FROM information_schema.tables WHERE table_schema = 'public'",
+        )
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!(tables, 0);
+        Ok(())
+    }
+}
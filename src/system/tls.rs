@@ -0,0 +1,161 @@
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+};
+
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa, SanType};
+use serde::Serialize;
+
+use super::pg_conf::PgConf;
+
+/// Where the certificates live, relative to the data directory
+pub const TLS_DIRECTORY: &str = "pmem-tls";
+
+/// The throwaway certificate authority and the certificates it signed for an
+/// embedded cluster. Paths are absolute so they can go straight into
+/// connection URIs.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct TlsFiles {
+    pub ca_cert: PathBuf,
+    pub server_cert: PathBuf,
+    pub server_key: PathBuf,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+}
+
+impl TlsFiles {
+    pub fn new(database_dir: &Path, client_certificates: bool) -> Self {
+        let dir = database_dir.join(TLS_DIRECTORY);
+        Self {
+            ca_cert: dir.join("ca.crt"),
+            server_cert: dir.join("server.crt"),
+            server_key: dir.join("server.key"),
+            client_cert: Some(dir.join("client.crt")).filter(|_| client_certificates),
+            client_key: Some(dir.join("client.key")).filter(|_| client_certificates),
+        }
+    }
+
+    fn all(&self) -> impl Iterator<Item = &PathBuf> {
+        IntoIterator::into_iter([&self.ca_cert, &self.server_cert, &self.server_key])
+            .chain(self.client_cert.iter())
+            .chain(self.client_key.iter())
+    }
+
+    /// Generate a CA plus certificates for `localhost`, unless a previous
+    /// run of a persistent cluster already did. Clients may have the CA
+    /// from back then, so it's kept rather than replaced.
+    pub fn ensure(&self, username: &str) -> anyhow::Result<()> {
+        if self.all().all(|p| p.exists()) {
+            return Ok(());
+        }
+        log::info!("Generating TLS certificates for the embedded cluster");
+        fs::create_dir_all(self.ca_cert.parent().unwrap())?;
+
+        let mut ca_params = CertificateParams::default();
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "pmem CA");
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = Certificate::from_params(ca_params)?;
+        write_file(&self.ca_cert, &ca.serialize_pem()?, 0o644)?;
+
+        let mut server_params = CertificateParams::new(vec!["localhost".to_string()]);
+        server_params.subject_alt_names.extend([
+            SanType::IpAddress(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            SanType::IpAddress(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+        ]);
+        server_params
+            .distinguished_name
+            .push(DnType::CommonName, "localhost");
+        let server = Certificate::from_params(server_params)?;
+        write_file(
+            &self.server_cert,
+            &server.serialize_pem_with_signer(&ca)?,
+            0o644,
+        )?;
+        // The server refuses to start with a key anyone else can read
+        write_file(&self.server_key, &server.serialize_private_key_pem(), 0o600)?;
+
+        if let (Some(cert), Some(key)) = (&self.client_cert, &self.client_key) {
+            // Certificate authentication maps the common name to the role
+            let mut client_params = CertificateParams::default();
+            client_params
+                .distinguished_name
+                .push(DnType::CommonName, username);
+            let client = Certificate::from_params(client_params)?;
+            write_file(cert, &client.serialize_pem_with_signer(&ca)?, 0o644)?;
+            write_file(key, &client.serialize_private_key_pem(), 0o600)?;
+        }
+        Ok(())
+    }
+
+    pub fn configure(&self, conf: &mut PgConf) {
+        conf.set("ssl", "on")
+            .set("ssl_cert_file", self.server_cert.to_string_lossy())
+            .set("ssl_key_file", self.server_key.to_string_lossy())
+            .set("ssl_ca_file", self.ca_cert.to_string_lossy());
+    }
+
+    /// Query parameters that make clients verify the server, and present
+    /// their own certificate when the cluster asks for one
+    pub fn uri_params(&self) -> Vec<(&'static str, String)> {
+        let mut params = vec![
+            ("sslmode", "verify-full".to_string()),
+            ("sslrootcert", self.ca_cert.to_string_lossy().into_owned()),
+        ];
+        if let (Some(cert), Some(key)) = (&self.client_cert, &self.client_key) {
+            params.push(("sslcert", cert.to_string_lossy().into_owned()));
+            params.push(("sslkey", key.to_string_lossy().into_owned()));
+        }
+        params
+    }
+}
+
+/// `mode` only applies on unix
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn write_file(path: &Path, contents: &str, mode: u32) -> anyhow::Result<()> {
+    let _ = fs::remove_file(path);
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(mode);
+    let mut file = options.open(path)?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_tls_files_are_generated_once_with_private_keys() {
+        let dir = TempDir::new("tls").unwrap();
+        let files = TlsFiles::new(dir.path(), true);
+        files.ensure("postgres").unwrap();
+
+        #[cfg(unix)]
+        for path in [&files.server_key, files.client_key.as_ref().unwrap()] {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o077, 0, "{:?} is readable by others", path);
+        }
+        let ca = fs::read_to_string(&files.ca_cert).unwrap();
+        assert!(ca.starts_with("-----BEGIN CERTIFICATE-----"));
+
+        files.ensure("postgres").unwrap();
+        assert_eq!(fs::read_to_string(&files.ca_cert).unwrap(), ca);
+
+        let params = files.uri_params();
+        assert_eq!(params[0], ("sslmode", "verify-full".to_string()));
+        assert_eq!(params.len(), 4);
+        assert_eq!(TlsFiles::new(dir.path(), false).uri_params().len(), 2);
+    }
+}
@@ -4,6 +4,23 @@ use std::time::Duration;
 pub fn deserialize_optional_datetime_from_sec<'de, D>(
     deserializer: D,
 ) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(deserialize_optional_number(deserializer)?.map(Duration::from_secs))
+}
+
+pub fn deserialize_optional_duration_from_millis<'de, D>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(deserialize_optional_number(deserializer)?.map(Duration::from_millis))
+}
+
+/// A number, strings are taken as nothing
+fn deserialize_optional_number<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -16,10 +33,7 @@ where
 
     match StringOrInt::<u64>::deserialize(deserializer)? {
         StringOrInt::String(_s) => Ok(None),
-        StringOrInt::Number(n) => {
-            let duration = Duration::from_secs(n);
-            Ok(Some(duration))
-        }
+        StringOrInt::Number(n) => Ok(Some(n)),
     }
 }
 
@@ -33,6 +47,11 @@ mod test {
     struct TestStruct {
         #[serde(default, deserialize_with = "deserialize_optional_datetime_from_sec")]
         pub timestamp: Option<Duration>,
+        #[serde(
+            default,
+            deserialize_with = "deserialize_optional_duration_from_millis"
+        )]
+        pub interval: Option<Duration>,
         pub port: Option<i16>,
     }
 
@@ -45,6 +64,12 @@ mod test {
             Some(Duration::from_secs(1234))
         );
         serde_json_eq!(TestStruct, "{\"timestamp\":\"1234\"}", timestamp, None);
+        serde_json_eq!(
+            TestStruct,
+            "{\"interval\":250}",
+            interval,
+            Some(Duration::from_millis(250))
+        );
     }
 
     #[test]
@@ -1,9 +1,11 @@
 mod deserializer;
 mod mac;
 mod migration_utils;
+#[cfg(feature = "node")]
 mod runtime_utils;
 
 pub use deserializer::*;
 pub use mac::*;
 pub use migration_utils::*;
+#[cfg(feature = "node")]
 pub use runtime_utils::*;
@@ -0,0 +1,109 @@
+use std::{fs, path::PathBuf};
+
+use tempdir::TempDir;
+
+use super::shutdown;
+
+/// The root directory of an embedded cluster. One we made up ourselves is
+/// removed with the cluster unless it has to outlive it, either because the
+/// cluster is persistent or because starting or stopping it failed and
+/// `keep_on_failure` asks to leave it for a post-mortem. A `root_path` given
+/// in the configuration belongs to the caller and is never removed.
+///
+/// Removal happens on stop, on drop, or when the process exits before either.
+#[derive(Debug)]
+pub struct Workspace {
+    pub path: PathBuf,
+    /// We created the directory, so it's ours to remove
+    temporary: bool,
+    persistent: bool,
+    keep_on_failure: bool,
+    failed: bool,
+}
+
+impl Workspace {
+    pub fn new(
+        root_path: Option<&str>,
+        persistent: bool,
+        keep_on_failure: bool,
+    ) -> anyhow::Result<Self> {
+        let (path, temporary) = match root_path {
+            Some(path) => {
+                fs::create_dir_all(path)?;
+                (PathBuf::from(path), false)
+            }
+            // Keep the directory, cleaning up is up to us from here on
+            None => (TempDir::new("pmem")?.into_path(), true),
+        };
+        log::info!("Using the workspace in {:?}", path);
+        if temporary && !persistent {
+            shutdown::remove_on_exit(path.clone());
+        }
+        Ok(Self {
+            path,
+            temporary,
+            persistent,
+            keep_on_failure,
+            failed: false,
+        })
+    }
+
+    pub fn keeps_failures(&self) -> bool {
+        self.keep_on_failure
+    }
+
+    pub fn mark_failed(&mut self) {
+        self.failed = true;
+        if self.keep_on_failure {
+            shutdown::forget_removal(&self.path);
+        }
+    }
+
+    /// Remove the directory if it's ours and nothing needs it anymore. Only
+    /// call this once the cluster is down.
+    pub fn clean_up(&mut self) {
+        if !self.temporary || self.persistent || !self.path.exists() {
+            return;
+        }
+        if self.failed && self.keep_on_failure {
+            log::warn!("Keeping the workspace in {:?} for debugging", self.path);
+            return;
+        }
+        shutdown::forget_removal(&self.path);
+        log::debug!("Removing the workspace in {:?}", self.path);
+        if let Err(e) = fs::remove_dir_all(&self.path) {
+            log::warn!("Unable to remove {:?}: {:?}", self.path, e.to_string());
+        }
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        self.clean_up();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_workspace_only_removes_temporary_directories() {
+        let mut workspace = Workspace::new(None, false, false).unwrap();
+        assert!(workspace.path.is_dir());
+        workspace.clean_up();
+        assert!(!workspace.path.exists());
+
+        let mut workspace = Workspace::new(None, false, true).unwrap();
+        workspace.mark_failed();
+        workspace.clean_up();
+        assert!(workspace.path.is_dir());
+        fs::remove_dir_all(&workspace.path).unwrap();
+
+        let dir = TempDir::new("workspace").unwrap();
+        let root_path = dir.path().join("root");
+        let mut workspace = Workspace::new(root_path.to_str(), false, false).unwrap();
+        workspace.clean_up();
+        assert!(root_path.is_dir());
+    }
+}
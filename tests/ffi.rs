@@ -0,0 +1,33 @@
+//! Compiles `tests/ffi/lifecycle.c` against the cdylib and `include/pmem.h`
+//! and runs it
+#![cfg(feature = "ffi")]
+
+use std::{env, path::PathBuf, process::Command};
+
+#[test]
+fn test_ffi_lifecycle_from_c() {
+    let manifest = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    // The cdylib built along with this test, next to it in target/<profile>/deps
+    let lib_dir = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    let program = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("pmem_lifecycle");
+
+    let status = Command::new(env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .arg(manifest.join("tests/ffi/lifecycle.c"))
+        .arg("-I")
+        .arg(manifest.join("include"))
+        .arg("-L")
+        .arg(&lib_dir)
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .arg("-lpmem")
+        .arg("-o")
+        .arg(&program)
+        .status()
+        .expect("Unable to run the C compiler");
+    assert!(status.success(), "lifecycle.c didn't compile");
+
+    let status = Command::new(&program)
+        .arg(manifest.join("test/fixtures/migrations"))
+        .status()
+        .unwrap();
+    assert!(status.success(), "lifecycle exited with {}", status);
+}
@@ -0,0 +1,52 @@
+/* The full C lifecycle, tests/ffi.rs builds and runs it against the cdylib */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "pmem.h"
+
+#define CHECK(cond)                                                          \
+    do {                                                                     \
+        if (!(cond)) {                                                       \
+            const char *error = pmem_last_error();                           \
+            fprintf(stderr, "%s:%d: %s failed: %s\n", __FILE__, __LINE__,    \
+                    #cond, error ? error : "no error");                      \
+            return 1;                                                        \
+        }                                                                    \
+    } while (0)
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s <migrations dir>\n", argv[0]);
+        return 2;
+    }
+
+    CHECK(pmem_init("{\"port\": ") == NULL);
+    CHECK(pmem_last_error() != NULL);
+
+    PmemHandle *handle = pmem_init("{\"generate_password\": true}");
+    CHECK(handle != NULL);
+    CHECK(pmem_start(handle) == 0);
+    CHECK(pmem_start(handle) == -1);
+
+    char *uri = pmem_create_db(handle, "ffi_app");
+    CHECK(uri != NULL);
+    CHECK(strstr(uri, "/ffi_app") != NULL);
+    pmem_string_free(uri);
+    CHECK(pmem_migrate(handle, "ffi_app", argv[1]) == 0);
+    CHECK(pmem_last_error() == NULL);
+
+    uri = pmem_create_db(handle, NULL);
+    CHECK(uri != NULL);
+    pmem_string_free(uri);
+
+    CHECK(pmem_drop_db(handle, "ffi_app") == 0);
+    CHECK(pmem_drop_db(handle, "ffi_app") == -1);
+    CHECK(pmem_migrate(handle, "ffi_app", argv[1]) == -1);
+
+    CHECK(pmem_stop(handle) == 0);
+    CHECK(pmem_create_db(handle, NULL) == NULL);
+    pmem_free(handle);
+    pmem_free(NULL);
+    return 0;
+}